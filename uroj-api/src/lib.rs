@@ -1,13 +1,19 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration as StdDuration};
 
-use actix_web::web;
+use actix_web::{rt::time::interval, web};
 use async_graphql::{dataloader::DataLoader, Context, EmptySubscription, Schema};
 use models::{AppSchema, Mutation, Query, UserLoader};
+use runtime::{HttpRuntimeClient, RuntimeClient};
 
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-
+use chrono::{Duration, Utc};
+use log::{info, warn};
 use uroj_db::connection::{Conn, PgPool};
+use uroj_db::models::executor::Executor as ExecutorData;
+
+pub use uroj_common::utils::get_random_token;
+
+//執行器超過該時長沒有心跳則視爲失效
+pub const EXECUTOR_TIMEOUT_SECS: i64 = 30;
 
 pub fn configure_service(cfg: &mut web::ServiceConfig) {
     cfg.service(handlers::index_playground)
@@ -15,6 +21,10 @@ pub fn configure_service(cfg: &mut web::ServiceConfig) {
 }
 
 pub fn create_schema_with_context(pool: PgPool) -> AppSchema {
+    create_schema_with_runtime(pool, Arc::new(HttpRuntimeClient::default()))
+}
+
+pub fn create_schema_with_runtime(pool: PgPool, runtime: Arc<dyn RuntimeClient>) -> AppSchema {
     let arc_pool = Arc::new(pool);
     let details_data_loader = DataLoader::new(UserLoader {
         pool: arc_pool.clone(),
@@ -25,6 +35,7 @@ pub fn create_schema_with_context(pool: PgPool) -> AppSchema {
         .enable_federation()
         .data(arc_pool)
         .data(details_data_loader)
+        .data(runtime)
         .finish()
 }
 
@@ -35,14 +46,29 @@ pub fn get_conn_from_ctx(ctx: &Context<'_>) -> Conn {
         .expect("Can't get DB connection")
 }
 
-pub fn get_random_token() -> String {
-    thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(6)
-        .map(char::from)
-        .collect::<String>()
-        .to_uppercase()
+//定時將失去心跳的執行器標記爲不可用
+pub fn spawn_executor_monitor(pool: PgPool) {
+    actix_web::rt::spawn(async move {
+        let mut interval = interval(StdDuration::from_secs(EXECUTOR_TIMEOUT_SECS as u64));
+        loop {
+            interval.tick().await;
+            let conn = match pool.get() {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("executor monitor cannot get DB connection: {}", e);
+                    continue;
+                }
+            };
+            let since = Utc::now().naive_utc() - Duration::seconds(EXECUTOR_TIMEOUT_SECS);
+            match ExecutorData::mark_dead(since, &conn) {
+                Ok(0) => {}
+                Ok(n) => info!("marked {} executors unavailable", n),
+                Err(e) => warn!("executor monitor failed: {}", e),
+            }
+        }
+    });
 }
 
 mod handlers;
 pub mod models;
+pub mod runtime;
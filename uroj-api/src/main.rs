@@ -1,7 +1,7 @@
 use actix_cors::Cors;
 use actix_web::{App, HttpServer};
 use dotenv::dotenv;
-use uroj_api::{configure_service, create_schema_with_context};
+use uroj_api::{configure_service, create_schema_with_context, spawn_executor_monitor};
 use uroj_db::connection::create_connection_pool;
 use uroj_db::run_migrations;
 
@@ -10,6 +10,7 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
     let pool = create_connection_pool();
     run_migrations(&pool);
+    spawn_executor_monitor(pool.clone());
 
     let schema = create_schema_with_context(pool);
 
@@ -1,10 +1,16 @@
 use async_graphql::*;
+use chrono::{DateTime, Local, TimeZone};
 use uroj_db::models::executor::Executor as ExecutorData;
 
 #[derive(SimpleObject)]
 pub struct Executor {
     id: i32,
     addr: String,
+    is_available: bool,
+    last_heartbeat: Option<DateTime<Local>>,
+    running_instances: i32,
+    cpu_usage: f32,
+    mem_usage: f32,
 }
 
 impl From<&ExecutorData> for Executor {
@@ -12,6 +18,20 @@ impl From<&ExecutorData> for Executor {
         Executor {
             id: executor_data.id,
             addr: executor_data.addr.clone(),
+            is_available: executor_data.is_available,
+            last_heartbeat: executor_data
+                .last_heartbeat_at
+                .map(|t| Local.from_utc_datetime(&t)),
+            running_instances: executor_data.running_instances,
+            cpu_usage: executor_data.cpu_usage,
+            mem_usage: executor_data.mem_usage,
         }
     }
 }
+
+#[derive(InputObject)]
+pub(crate) struct HeartbeatInput {
+    pub running_instances: i32,
+    pub cpu_usage: f32,
+    pub mem_usage: f32,
+}
@@ -26,6 +26,8 @@ pub struct Instance {
     pub begin_at: DateTime<Local>,
     pub executor_id: i32,
     pub token: String, //给别人以访问
+    pub end_at: Option<DateTime<Local>>,
+    pub penalty: i32, //險情扣分
 }
 
 #[ComplexObject]
@@ -58,6 +60,8 @@ impl From<&InstanceData> for Instance {
             begin_at: Local.from_utc_datetime(&data.begin_at),
             executor_id: data.executor_id,
             token: data.token.clone(),
+            end_at: data.end_at.map(|t| Local.from_utc_datetime(&t)),
+            penalty: data.penalty,
         }
     }
 }
@@ -67,11 +71,12 @@ pub(crate) struct InstanceInput {
     pub title: String,
     pub description: Option<String>,
     pub player: String,
-    pub station_id: i32,  //指定station的副本
-    pub executor_id: i32, //指定
+    pub station_id: i32,                 //指定station的副本
+    pub executor_id: Option<i32>,        //指定，缺省則自動分配
+    pub end_at: Option<DateTime<Local>>, //缺省則不自動結束
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Enum, Display, EnumString)]
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Enum, Display, EnumString, Debug)]
 #[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
 pub enum InstanceStatus {
     Prestart, //启动前
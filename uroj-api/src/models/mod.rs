@@ -4,8 +4,9 @@ use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use async_graphql::{async_trait, dataloader::Loader, guard::Guard, Context, Object, Result};
 use async_graphql::{EmptySubscription, Error, Schema};
-use uroj_common::utils::{Claims, Role as AuthRole};
-use uroj_db::connection::PgPool;
+use chrono::{Duration, Utc};
+use uroj_common::utils::{create_token, Claims, Role as AuthRole};
+use uroj_db::connection::{Conn, PgPool};
 use uroj_db::models::class::Class as ClassData;
 use uroj_db::models::executor::{Executor as ExecutorData, NewExecutor as NewExecutorData};
 use uroj_db::models::instance::{Instance as InstanceData, NewInstance as NewInstanceData};
@@ -18,10 +19,11 @@ use user::User;
 use station::Station;
 use uuid::Uuid;
 
-use crate::{get_conn_from_ctx, get_random_token};
+use crate::runtime::{start_on_executor, stop_on_executor, InstanceEndpoint, RuntimeClient};
+use crate::{get_conn_from_ctx, get_random_token, EXECUTOR_TIMEOUT_SECS};
 
 use self::{
-    executor::Executor,
+    executor::{Executor, HeartbeatInput},
     instance::{Instance, InstanceInput, InstanceStatus},
     station::StationInput,
 };
@@ -134,6 +136,45 @@ impl Mutation {
         Ok(created_executor.into())
     }
 
+    //運行時啓動時註冊自己，已存在的地址則沿用原記錄
+    #[graphql(guard(RoleGuard(role = "AuthRole::Executor")))]
+    async fn register_executor(&self, ctx: &Context<'_>, url: String) -> Result<Executor> {
+        check_executor_addr(ctx, &url)?;
+        let conn = get_conn_from_ctx(ctx);
+        let executor = match ExecutorData::find_by_addr(&url, &conn) {
+            Ok(executor) => executor,
+            Err(_) => NewExecutorData { addr: url }.create(&conn)?,
+        };
+        let registered = &executor.update_heartbeat(
+            executor.running_instances,
+            executor.cpu_usage,
+            executor.mem_usage,
+            Utc::now().naive_utc(),
+            &conn,
+        )?;
+        Ok(registered.into())
+    }
+
+    #[graphql(guard(RoleGuard(role = "AuthRole::Executor")))]
+    async fn heartbeat(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        input: HeartbeatInput,
+    ) -> Result<Executor> {
+        let conn = get_conn_from_ctx(ctx);
+        let executor = ExecutorData::find_one(id, &conn)?;
+        check_executor_addr(ctx, &executor.addr)?;
+        let executor = &executor.update_heartbeat(
+            input.running_instances,
+            input.cpu_usage,
+            input.mem_usage,
+            Utc::now().naive_utc(),
+            &conn,
+        )?;
+        Ok(executor.into())
+    }
+
     async fn create_instance(&self, ctx: &Context<'_>, input: InstanceInput) -> Result<Instance> {
         let id = get_id_from_ctx(ctx).ok_or("Invalid token")?;
         let conn = get_conn_from_ctx(ctx);
@@ -147,13 +188,58 @@ impl Mutation {
             player_id: input.player,
             station_id: station.id,
             curr_state: InstanceStatus::default().to_string(),
-            executor_id: input.executor_id,
+            executor_id: match input.executor_id {
+                Some(eid) => eid,
+                None => pick_executor(&conn)?.id,
+            },
             token: get_random_token(),
+            begin_at: None,
+            end_at: input.end_at.map(|t| t.naive_utc()),
         };
 
         let created_instance = &new_instance.create(&conn)?;
         Ok(created_instance.into())
     }
+
+    //在分配的執行器上啓動實例，實例狀態由執行器寫回
+    #[graphql(guard(LoginGaurd()))]
+    async fn start_instance(&self, ctx: &Context<'_>, id: String) -> Result<InstanceEndpoint> {
+        let conn = get_conn_from_ctx(ctx);
+        let instance = InstanceData::find_one(Uuid::from_str(&id)?, &conn)?;
+        let executor = ExecutorData::find_one(instance.executor_id, &conn)?;
+
+        let client = ctx.data_unchecked::<Arc<dyn RuntimeClient>>();
+        Ok(start_on_executor(client.as_ref(), &executor, &id, &get_token_from_ctx(ctx)?).await?)
+    }
+
+    //在分配的執行器上結束實例，實例狀態由執行器寫回
+    #[graphql(guard(LoginGaurd()))]
+    async fn stop_instance(&self, ctx: &Context<'_>, id: String) -> Result<InstanceEndpoint> {
+        let conn = get_conn_from_ctx(ctx);
+        let instance = InstanceData::find_one(Uuid::from_str(&id)?, &conn)?;
+        let executor = ExecutorData::find_one(instance.executor_id, &conn)?;
+
+        let client = ctx.data_unchecked::<Arc<dyn RuntimeClient>>();
+        Ok(stop_on_executor(client.as_ref(), &executor, &id, &get_token_from_ctx(ctx)?).await?)
+    }
+}
+
+//執行器只能以自己的地址註冊和上報
+fn check_executor_addr(ctx: &Context<'_>, addr: &str) -> Result<()> {
+    let sub = get_id_from_ctx(ctx).ok_or("Not Login")?;
+    match sub.strip_prefix("executor@") {
+        Some(a) if a == addr => Ok(()),
+        _ => Err("Forbiden".into()),
+    }
+}
+
+//選擇負載最低的健康執行器
+fn pick_executor(conn: &Conn) -> Result<ExecutorData> {
+    let since = Utc::now().naive_utc() - Duration::seconds(EXECUTOR_TIMEOUT_SECS);
+    ExecutorData::list_healthy(since, conn)?
+        .into_iter()
+        .next()
+        .ok_or("no available executor".into())
 }
 
 pub(crate) struct RoleGuard {
@@ -163,15 +249,10 @@ pub(crate) struct RoleGuard {
 #[async_trait::async_trait]
 impl Guard for RoleGuard {
     async fn check(&self, ctx: &Context<'_>) -> Result<()> {
-        match get_role_from_ctx(ctx) {
-            Some(role) => {
-                if role == self.role {
-                    Ok(())
-                } else {
-                    Err("Forbiden".into())
-                }
-            }
-            None => Err("Not Login".into()),
+        if get_role_from_ctx(ctx)? == self.role {
+            Ok(())
+        } else {
+            Err("Forbiden".into())
         }
     }
 }
@@ -189,9 +270,17 @@ fn get_id_from_ctx(ctx: &Context<'_>) -> Option<String> {
     ctx.data_opt::<Claims>().map(|c| c.sub.clone())
 }
 
-fn get_role_from_ctx(ctx: &Context<'_>) -> Option<AuthRole> {
-    ctx.data_opt::<Claims>()
-        .map(|c| AuthRole::from_str(&c.role).expect("Cannot parse authrole"))
+//以調用者的身份訪問運行時
+fn get_token_from_ctx(ctx: &Context<'_>) -> Result<String> {
+    let id = get_id_from_ctx(ctx).ok_or("Not Login")?;
+    let role = get_role_from_ctx(ctx)?;
+    Ok(create_token(id, role))
+}
+
+//令牌中的角色無法識別時返回錯誤而不是崩潰
+fn get_role_from_ctx(ctx: &Context<'_>) -> Result<AuthRole> {
+    let claims = ctx.data_opt::<Claims>().ok_or("Not Login")?;
+    AuthRole::from_str(&claims.role).map_err(|_| "Unknown role".into())
 }
 
 pub struct UserLoader {
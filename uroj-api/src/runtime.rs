@@ -0,0 +1,365 @@
+use std::str::FromStr;
+
+use async_graphql::{async_trait, SimpleObject};
+use serde_json::{json, Value};
+use uroj_db::models::executor::Executor as ExecutorData;
+
+use crate::models::instance::InstanceStatus;
+
+//執行器上運行時的接口，測試時可以換成進程内的替身
+#[async_trait::async_trait]
+pub trait RuntimeClient: Send + Sync {
+    async fn run(&self, addr: &str, id: &str, token: &str) -> Result<String, String>;
+    async fn stop(&self, addr: &str, id: &str, token: &str) -> Result<String, String>;
+}
+
+#[derive(Default)]
+pub struct HttpRuntimeClient {
+    client: reqwest::Client,
+}
+
+impl HttpRuntimeClient {
+    async fn call(&self, addr: &str, token: &str, query: &str, id: &str) -> Result<Value, String> {
+        let body: Value = self
+            .client
+            .post(addr)
+            .bearer_auth(token)
+            .json(&json!({ "query": query, "variables": { "id": id } }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let Some(errors) = body.get("errors") {
+            return Err(errors.to_string());
+        }
+        Ok(body["data"].clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl RuntimeClient for HttpRuntimeClient {
+    async fn run(&self, addr: &str, id: &str, token: &str) -> Result<String, String> {
+        let data = self
+            .call(addr, token, "mutation ($id: String!) { run(id: $id) }", id)
+            .await?;
+        data["run"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or("invalid response".to_string())
+    }
+
+    async fn stop(&self, addr: &str, id: &str, token: &str) -> Result<String, String> {
+        let data = self
+            .call(addr, token, "mutation ($id: String!) { stop(id: $id) }", id)
+            .await?;
+        data["stop"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or("invalid response".to_string())
+    }
+}
+
+//客户端連接運行時所需的信息
+#[derive(SimpleObject, Debug, PartialEq)]
+pub struct InstanceEndpoint {
+    pub state: InstanceStatus,
+    pub endpoint: String,    //graphql
+    pub ws_endpoint: String, //subscription
+}
+
+//失聯或被停用的執行器不再接受操作
+fn check_available(executor: &ExecutorData) -> Result<(), String> {
+    if executor.is_available {
+        Ok(())
+    } else {
+        Err(format!("executor {} is not available", executor.id))
+    }
+}
+
+impl InstanceEndpoint {
+    fn new(state: &str, executor: &ExecutorData) -> Result<Self, String> {
+        Ok(InstanceEndpoint {
+            state: InstanceStatus::from_str(state).map_err(|e| e.to_string())?,
+            endpoint: executor.addr.clone(),
+            ws_endpoint: executor.addr.replacen("http", "ws", 1),
+        })
+    }
+}
+
+pub(crate) async fn start_on_executor(
+    client: &dyn RuntimeClient,
+    executor: &ExecutorData,
+    id: &str,
+    token: &str,
+) -> Result<InstanceEndpoint, String> {
+    check_available(executor)?;
+    let state = client.run(&executor.addr, id, token).await?;
+    InstanceEndpoint::new(&state, executor)
+}
+
+pub(crate) async fn stop_on_executor(
+    client: &dyn RuntimeClient,
+    executor: &ExecutorData,
+    id: &str,
+    token: &str,
+) -> Result<InstanceEndpoint, String> {
+    check_available(executor)?;
+    let state = client.stop(&executor.addr, id, token).await?;
+    InstanceEndpoint::new(&state, executor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, sync::Arc, sync::Mutex};
+
+    use async_graphql::Request;
+    use chrono::{Duration, Utc};
+    use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+    use diesel::{Connection, PgConnection};
+    use uroj_common::utils::Claims;
+    use uroj_db::connection::PgPool;
+    use uroj_db::models::executor::NewExecutor;
+    use uroj_db::models::station::NewStation;
+    use uroj_db::models::user::NewUser;
+
+    use crate::create_schema_with_runtime;
+
+    //進程内的替身執行器，只記錄正在運行的實例
+    #[derive(Default)]
+    struct LocalRuntime {
+        running: Mutex<HashSet<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RuntimeClient for LocalRuntime {
+        async fn run(&self, _addr: &str, id: &str, _token: &str) -> Result<String, String> {
+            if !self.running.lock().unwrap().insert(id.to_string()) {
+                return Err(format!("instance {} is already running", id));
+            }
+            Ok(InstanceStatus::Playing.to_string())
+        }
+
+        async fn stop(&self, _addr: &str, id: &str, _token: &str) -> Result<String, String> {
+            if !self.running.lock().unwrap().remove(id) {
+                return Err(format!("not found instance {}", id));
+            }
+            Ok(InstanceStatus::Finished.to_string())
+        }
+    }
+
+    fn executor() -> ExecutorData {
+        ExecutorData {
+            id: 1,
+            addr: "http://localhost:8003/".to_string(),
+            is_available: true,
+            last_heartbeat_at: None,
+            running_instances: 0,
+            cpu_usage: 0.,
+            mem_usage: 0.,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_start_and_stop_instance() {
+        let runtime = LocalRuntime::default();
+        let executor = executor();
+
+        let endpoint = start_on_executor(&runtime, &executor, "i1", "t")
+            .await
+            .unwrap();
+        assert_eq!(endpoint.state, InstanceStatus::Playing);
+        assert_eq!(endpoint.endpoint, "http://localhost:8003/");
+        assert_eq!(endpoint.ws_endpoint, "ws://localhost:8003/");
+
+        assert!(start_on_executor(&runtime, &executor, "i1", "t")
+            .await
+            .is_err());
+
+        let endpoint = stop_on_executor(&runtime, &executor, "i1", "t")
+            .await
+            .unwrap();
+        assert_eq!(endpoint.state, InstanceStatus::Finished);
+        assert!(stop_on_executor(&runtime, &executor, "i1", "t")
+            .await
+            .is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_unavailable_executor() {
+        let runtime = LocalRuntime::default();
+        let mut executor = executor();
+        executor.is_available = false;
+
+        let result = start_on_executor(&runtime, &executor, "i1", "t").await;
+        assert_eq!(
+            result.err(),
+            Some("executor 1 is not available".to_string())
+        );
+        assert!(runtime.running.lock().unwrap().is_empty());
+
+        runtime.running.lock().unwrap().insert("i1".to_string());
+        let result = stop_on_executor(&runtime, &executor, "i1", "t").await;
+        assert_eq!(
+            result.err(),
+            Some("executor 1 is not available".to_string())
+        );
+        assert!(runtime.running.lock().unwrap().contains("i1"));
+    }
+
+    //守衛在解析器之前拒絕，不會用到數據庫連接
+    #[actix_rt::test]
+    async fn test_guards_without_login() {
+        let pool = Pool::builder()
+            .build_unchecked(ConnectionManager::<PgConnection>::new("postgres://unused"));
+        let schema = create_schema_with_runtime(pool, Arc::new(LocalRuntime::default()));
+
+        let resp = schema
+            .execute(Request::new(
+                r#"mutation { startInstance(id: "i1") { state } }"#,
+            ))
+            .await;
+        assert_eq!(resp.errors[0].message, "Not Login");
+
+        let claims = Claims {
+            sub: "someone".to_string(),
+            exp: (Utc::now() + Duration::hours(1)).timestamp(),
+            role: "NOBODY".to_string(),
+        };
+        let resp = schema
+            .execute(Request::new("{ classes { id } }").data(claims))
+            .await;
+        assert_eq!(resp.errors[0].message, "Unknown role");
+    }
+
+    #[actix_rt::test]
+    async fn test_register_other_executor() {
+        let pool = Pool::builder()
+            .build_unchecked(ConnectionManager::<PgConnection>::new("postgres://unused"));
+        let schema = create_schema_with_runtime(pool, Arc::new(LocalRuntime::default()));
+
+        let claims = Claims {
+            sub: "executor@http://a:8003/".to_string(),
+            exp: (Utc::now() + Duration::hours(1)).timestamp(),
+            role: "EXECUTOR".to_string(),
+        };
+        let resp = schema
+            .execute(
+                Request::new(r#"mutation { registerExecutor(url: "http://b:8003/") { id } }"#)
+                    .data(claims),
+            )
+            .await;
+        assert_eq!(resp.errors[0].message, "Forbiden");
+    }
+
+    //測試數據都在一個不提交的事務中，只用一個連接
+    #[derive(Debug)]
+    struct TestTransaction;
+
+    impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for TestTransaction {
+        fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+            conn.begin_test_transaction()
+                .map_err(diesel::r2d2::Error::QueryError)
+        }
+    }
+
+    fn test_pool() -> PgPool {
+        let db_url = std::env::var("DATABASE_URL").expect("Can't get DB URL");
+        Pool::builder()
+            .max_size(1)
+            .connection_customizer(Box::new(TestTransaction))
+            .build(ConnectionManager::<PgConnection>::new(db_url))
+            .expect("Failed to create pool")
+    }
+
+    fn request(query: String) -> Request {
+        Request::new(query).data(Claims {
+            sub: "teacher".to_string(),
+            exp: (Utc::now() + Duration::hours(1)).timestamp(),
+            role: "ADMIN".to_string(),
+        })
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a database in DATABASE_URL"]
+    async fn test_start_and_stop_through_schema() {
+        std::env::set_var("JWT_SECRET_KEY", "test");
+        let pool = test_pool();
+        let conn = pool.get().unwrap();
+        let schema = create_schema_with_runtime(pool.clone(), Arc::new(LocalRuntime::default()));
+
+        NewUser {
+            id: "teacher".to_string(),
+            hash_pwd: String::new(),
+            email: "teacher@example.com".to_string(),
+            class_id: None,
+            user_role: "ADMIN".to_string(),
+        }
+        .create(&conn)
+        .unwrap();
+        let station = NewStation {
+            title: "test".to_string(),
+            description: None,
+            draft: false,
+            author_id: None,
+            yaml: String::new(),
+        }
+        .create(&conn)
+        .unwrap();
+        //負載較低的執行器優先
+        let now = Utc::now().naive_utc();
+        NewExecutor {
+            addr: "http://busy:8003/".to_string(),
+        }
+        .create(&conn)
+        .unwrap()
+        .update_heartbeat(3, 0., 0., now, &conn)
+        .unwrap();
+        let idle = NewExecutor {
+            addr: "http://idle:8003/".to_string(),
+        }
+        .create(&conn)
+        .unwrap()
+        .update_heartbeat(1, 0., 0., now, &conn)
+        .unwrap();
+        drop(conn);
+
+        let create = format!(
+            r#"mutation {{ createInstance(input: {{ title: "t", player: "teacher", stationId: {} }}) {{ id executorId }} }}"#,
+            station.id
+        );
+        let resp = schema.execute(request(create.clone())).await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        let data = resp.data.into_json().unwrap();
+        assert_eq!(data["createInstance"]["executorId"], idle.id);
+        let id = data["createInstance"]["id"].as_str().unwrap().to_string();
+
+        let start = format!(
+            r#"mutation {{ startInstance(id: "{}") {{ state endpoint wsEndpoint }} }}"#,
+            id
+        );
+        let resp = schema.execute(request(start)).await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        let data = resp.data.into_json().unwrap();
+        assert_eq!(data["startInstance"]["state"], "PLAYING");
+        assert_eq!(data["startInstance"]["endpoint"], "http://idle:8003/");
+        assert_eq!(data["startInstance"]["wsEndpoint"], "ws://idle:8003/");
+
+        let stop = format!(r#"mutation {{ stopInstance(id: "{}") {{ state }} }}"#, id);
+        let resp = schema.execute(request(stop)).await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        let data = resp.data.into_json().unwrap();
+        assert_eq!(data["stopInstance"]["state"], "FINISHED");
+
+        //沒有健康的執行器時無法分配
+        let conn = pool.get().unwrap();
+        let later = Utc::now().naive_utc() + Duration::seconds(1);
+        ExecutorData::mark_dead(later, &conn).unwrap();
+        drop(conn);
+        let resp = schema.execute(request(create)).await;
+        assert_eq!(resp.errors[0].message, "no available executor");
+    }
+}
@@ -5,6 +5,8 @@ use chrono::{Duration, Local};
 use jsonwebtoken::{decode, DecodingKey, TokenData, Validation};
 use jsonwebtoken::{encode, EncodingKey, Header};
 use lazy_static::lazy_static;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use strum_macros::{Display, EnumString};
 
@@ -25,6 +27,7 @@ pub struct Claims {
 pub enum Role {
     Admin,
     User,
+    Executor, // runtime services reporting to the api
 }
 
 pub fn create_token(username: String, role: Role) -> String {
@@ -44,24 +47,34 @@ pub fn create_token(username: String, role: Role) -> String {
     .expect("Can't create token")
 }
 
+pub fn get_random_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(6)
+        .map(char::from)
+        .collect::<String>()
+        .to_uppercase()
+}
+
 pub fn get_claims(http_request: HttpRequest) -> Option<Claims> {
     http_request
         .headers()
         .get("Authorization")
-        .and_then(|header_value| {
-            header_value.to_str().ok().map(|s| {
-                let jwt_start_index = "Bearer ".len();
-                let jwt = s[jwt_start_index..s.len()].to_string();
-                decode_token(&jwt).claims
-            })
-        })
+        .and_then(|header_value| header_value.to_str().ok().and_then(get_claims_from_header))
+}
+
+// for websocket connections, which carry the header value in their init payload
+pub fn get_claims_from_header(header_value: &str) -> Option<Claims> {
+    let jwt = header_value.strip_prefix("Bearer ")?;
+    decode_token(jwt).map(|t| t.claims)
 }
 
-fn decode_token(token: &str) -> TokenData<Claims> {
+// a malformed or expired token is treated as unauthenticated
+fn decode_token(token: &str) -> Option<TokenData<Claims>> {
     decode::<Claims>(
         &token,
         &DecodingKey::from_secret(JWT_SECRET_KEY.as_ref()),
         &Validation::default(),
     )
-    .expect("Can't decode token")
+    .ok()
 }
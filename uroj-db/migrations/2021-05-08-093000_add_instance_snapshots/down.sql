@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE instances DROP COLUMN snapshot
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE instances ADD COLUMN snapshot text
\ No newline at end of file
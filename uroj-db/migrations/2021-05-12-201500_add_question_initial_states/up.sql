@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE questions ADD COLUMN initial_state text --初始局面(JSON)
\ No newline at end of file
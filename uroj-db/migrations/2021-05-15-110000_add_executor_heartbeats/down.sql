@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE executors
+    DROP COLUMN is_available,
+    DROP COLUMN last_heartbeat_at,
+    DROP COLUMN running_instances,
+    DROP COLUMN cpu_usage,
+    DROP COLUMN mem_usage
\ No newline at end of file
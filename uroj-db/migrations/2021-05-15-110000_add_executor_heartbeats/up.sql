@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE executors
+    ADD COLUMN is_available       boolean     not null default 't',
+    ADD COLUMN last_heartbeat_at  timestamp,
+    ADD COLUMN running_instances  int         not null default 0,
+    ADD COLUMN cpu_usage          real        not null default 0,
+    ADD COLUMN mem_usage          real        not null default 0
\ No newline at end of file
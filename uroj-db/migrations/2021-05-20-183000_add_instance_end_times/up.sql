@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE instances ADD COLUMN end_at timestamp
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE instances DROP COLUMN penalty
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE instances ADD COLUMN penalty integer NOT NULL DEFAULT 0
\ No newline at end of file
@@ -1,5 +1,6 @@
 use crate::schema::executors;
 use crate::schema::executors::dsl::*;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::PgConnection;
 use serde::{Deserialize, Serialize};
@@ -10,6 +11,11 @@ use serde::{Deserialize, Serialize};
 pub struct Executor {
     pub id: i32,
     pub addr: String,
+    pub is_available: bool,
+    pub last_heartbeat_at: Option<NaiveDateTime>,
+    pub running_instances: i32,
+    pub cpu_usage: f32,
+    pub mem_usage: f32,
 }
 
 impl Executor {
@@ -17,9 +23,52 @@ impl Executor {
         executors.find(eid).first(conn)
     }
 
+    pub fn find_by_addr(a: &str, conn: &PgConnection) -> QueryResult<Executor> {
+        executors.filter(addr.eq(a)).first(conn)
+    }
+
     pub fn list_all(conn: &PgConnection) -> QueryResult<Vec<Self>> {
         executors.load(conn)
     }
+
+    // healthy executors which reported after `since`, the least loaded first
+    pub fn list_healthy(since: NaiveDateTime, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        executors
+            .filter(is_available.eq(true))
+            .filter(last_heartbeat_at.ge(since))
+            .order((running_instances.asc(), cpu_usage.asc()))
+            .load(conn)
+    }
+
+    pub fn update_heartbeat(
+        &self,
+        running: i32,
+        cpu: f32,
+        mem: f32,
+        time: NaiveDateTime,
+        conn: &PgConnection,
+    ) -> QueryResult<Executor> {
+        diesel::update(self)
+            .set((
+                is_available.eq(true),
+                last_heartbeat_at.eq(time),
+                running_instances.eq(running),
+                cpu_usage.eq(cpu),
+                mem_usage.eq(mem),
+            ))
+            .get_result(conn)
+    }
+
+    // executors which have not reported since `since`, or never reported, are marked unavailable
+    pub fn mark_dead(since: NaiveDateTime, conn: &PgConnection) -> QueryResult<usize> {
+        diesel::update(
+            executors
+                .filter(is_available.eq(true))
+                .filter(last_heartbeat_at.lt(since).or(last_heartbeat_at.is_null())),
+        )
+        .set(is_available.eq(false))
+        .execute(conn)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Insertable)]
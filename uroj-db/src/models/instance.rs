@@ -19,6 +19,9 @@ pub struct Instance {
     pub begin_at: NaiveDateTime,
     pub executor_id: i32,
     pub token: String, //给别人以访问
+    pub snapshot: Option<String>, //運行時快照
+    pub end_at: Option<NaiveDateTime>, //到時自動結束
+    pub penalty: i32, //險情扣分，與各題得分分開記錄
 }
 
 impl Instance {
@@ -30,6 +33,10 @@ impl Instance {
         instances.load(conn)
     }
 
+    pub fn list_by_executor(eid: i32, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        instances.filter(executor_id.eq(eid)).load(conn)
+    }
+
     pub fn update_state(&self, new_state: String, conn: &PgConnection) -> QueryResult<String> {
         diesel::update(self)
             .set(curr_state.eq(new_state.clone()))
@@ -38,6 +45,24 @@ impl Instance {
         Ok(new_state)
     }
 
+    pub fn update_snapshot(
+        &self,
+        new_snapshot: Option<String>,
+        conn: &PgConnection,
+    ) -> QueryResult<()> {
+        diesel::update(self)
+            .set(snapshot.eq(new_snapshot))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn update_penalty(&self, new_penalty: i32, conn: &PgConnection) -> QueryResult<()> {
+        diesel::update(self)
+            .set(penalty.eq(new_penalty))
+            .execute(conn)?;
+        Ok(())
+    }
+
     pub fn get_scores(&self, conn: &PgConnection) -> QueryResult<Vec<InstanceQuestion>> {
         InstanceQuestion::belonging_to(self).load(conn)
     }
@@ -58,6 +83,8 @@ pub struct NewInstance {
     pub curr_state: String,
     pub executor_id: i32, //指定
     pub token: String,
+    pub begin_at: Option<NaiveDateTime>, //缺省爲創建時間
+    pub end_at: Option<NaiveDateTime>,
 }
 
 impl NewInstance {
@@ -16,6 +16,7 @@ pub struct Question {
     pub exam_id: i32,
     pub station_id: i32,
     pub score: i32,
+    pub initial_state: Option<String>, //初始局面
 }
 
 impl Question {
@@ -39,6 +40,7 @@ pub struct NewQuestion {
     pub exam_id: i32,
     pub station_id: i32,
     pub score: i32,
+    pub initial_state: Option<String>, //初始局面
 }
 
 impl NewQuestion {
@@ -17,6 +17,11 @@ table! {
     executors (id) {
         id -> Int4,
         addr -> Varchar,
+        is_available -> Bool,
+        last_heartbeat_at -> Nullable<Timestamp>,
+        running_instances -> Int4,
+        cpu_usage -> Float4,
+        mem_usage -> Float4,
     }
 }
 
@@ -41,6 +46,9 @@ table! {
         begin_at -> Timestamp,
         executor_id -> Int4,
         token -> Varchar,
+        snapshot -> Nullable<Text>,
+        end_at -> Nullable<Timestamp>,
+        penalty -> Int4,
     }
 }
 
@@ -55,6 +63,7 @@ table! {
         exam_id -> Int4,
         station_id -> Int4,
         score -> Int4,
+        initial_state -> Nullable<Text>,
     }
 }
 
@@ -0,0 +1,222 @@
+use std::fmt;
+
+#[cfg(feature = "graphql")]
+use async_graphql::{Error, ErrorExtensions};
+use serde::{Deserialize, Serialize};
+
+use crate::fsm::{NodeID, TrainID, TurnoutID};
+
+//聯鎖拒絕操作的原因，code穩定，供前端高亮和考試評分分類
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum InterlockingError {
+    UnknownNode(NodeID),
+    UnknownSignal(String),
+    UnknownButton(String),
+    UnknownTurnout(TurnoutID),
+    InvalidSignal(String), //無法推斷方向的信號機
+    SameStartEnd(String),
+    FilamentFused(String),     //始端信號機主燈絲斷絲
+    SignalOpen(String),        //始端信號機已開放
+    NoRouteFound(String),      //按鈕組合不構成進路
+    NoAvailablePath(String),   //站場中沒有符合方向的路徑
+    NodeNotVacant(NodeID),     //區段占用
+    NodeLocked(NodeID),        //區段已被其他進路鎖閉
+    NodeInUse(NodeID),         //區段被牴觸進路征用
+    TurnoutBlocked(TurnoutID), //道岔單鎖
+    RouteNotFound(String),     //始端信號機沒有已建立的進路
+    RouteOccupied(String),     //列車已進入進路，不能取消
+    HostileRoute(String),      //敵對進路已建立，記錄其始端信號機
+    ApproachNotVacant(NodeID), //接近區段有車
+    IncompleteRoute(NodeID),   //接近區段仍在鎖閉，進路未完整
+    UnknownStoredRoute(usize), //沒有該存儲進路
+    NotPassRoute(String),      //只有通過進路可以設爲自動
+    UnknownTrack(String),
+    NodeBlocked(NodeID),    //區段封鎖
+    SignalBlocked(String),  //按鈕封鎖
+    InvalidTrain(TrainID),  //快照中沒有位置的列車
+    RouteReleasing(String), //進路正在延時解鎖
+    NodeNotLocked(NodeID),  //區段不屬於任何鎖閉的進路
+    NotHomeSignal(String),  //只有進站信號機能開放引導
+}
+
+impl InterlockingError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            InterlockingError::UnknownNode(_) => "UNKNOWN_NODE",
+            InterlockingError::UnknownSignal(_) => "UNKNOWN_SIGNAL",
+            InterlockingError::UnknownButton(_) => "UNKNOWN_BUTTON",
+            InterlockingError::UnknownTurnout(_) => "UNKNOWN_TURNOUT",
+            InterlockingError::InvalidSignal(_) => "INVALID_SIGNAL",
+            InterlockingError::SameStartEnd(_) => "SAME_START_END",
+            InterlockingError::FilamentFused(_) => "FILAMENT_FUSED",
+            InterlockingError::SignalOpen(_) => "SIGNAL_OPEN",
+            InterlockingError::NoRouteFound(_) => "NO_ROUTE_FOUND",
+            InterlockingError::NoAvailablePath(_) => "NO_AVAILABLE_PATH",
+            InterlockingError::NodeNotVacant(_) => "NODE_NOT_VACANT",
+            InterlockingError::NodeLocked(_) => "NODE_LOCKED",
+            InterlockingError::NodeInUse(_) => "NODE_IN_USE",
+            InterlockingError::TurnoutBlocked(_) => "TURNOUT_BLOCKED",
+            InterlockingError::RouteNotFound(_) => "ROUTE_NOT_FOUND",
+            InterlockingError::RouteOccupied(_) => "ROUTE_OCCUPIED",
+            InterlockingError::HostileRoute(_) => "HOSTILE_ROUTE",
+            InterlockingError::ApproachNotVacant(_) => "APPROACH_NOT_VACANT",
+            InterlockingError::IncompleteRoute(_) => "INCOMPLETE_ROUTE",
+            InterlockingError::UnknownStoredRoute(_) => "UNKNOWN_STORED_ROUTE",
+            InterlockingError::NotPassRoute(_) => "NOT_PASS_ROUTE",
+            InterlockingError::UnknownTrack(_) => "UNKNOWN_TRACK",
+            InterlockingError::NodeBlocked(_) => "NODE_BLOCKED",
+            InterlockingError::SignalBlocked(_) => "SIGNAL_BLOCKED",
+            InterlockingError::InvalidTrain(_) => "INVALID_TRAIN",
+            InterlockingError::RouteReleasing(_) => "ROUTE_RELEASING",
+            InterlockingError::NodeNotLocked(_) => "NODE_NOT_LOCKED",
+            InterlockingError::NotHomeSignal(_) => "NOT_HOME_SIGNAL",
+        }
+    }
+
+    //占用、鎖閉和敵對會隨列車運行解除，這類原因可以存儲進路等待
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            InterlockingError::NodeNotVacant(_)
+                | InterlockingError::NodeLocked(_)
+                | InterlockingError::NodeInUse(_)
+                | InterlockingError::HostileRoute(_)
+                | InterlockingError::SignalOpen(_)
+                | InterlockingError::RouteOccupied(_)
+                | InterlockingError::RouteReleasing(_)
+        )
+    }
+
+    //出錯的區段
+    pub fn node(&self) -> Option<NodeID> {
+        match self {
+            InterlockingError::UnknownNode(id)
+            | InterlockingError::NodeNotVacant(id)
+            | InterlockingError::NodeLocked(id)
+            | InterlockingError::NodeInUse(id)
+            | InterlockingError::ApproachNotVacant(id)
+            | InterlockingError::IncompleteRoute(id)
+            | InterlockingError::NodeBlocked(id)
+            | InterlockingError::NodeNotLocked(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    //出錯的信號機，進路類錯誤爲始端信號機
+    pub fn signal(&self) -> Option<&str> {
+        match self {
+            InterlockingError::UnknownSignal(id)
+            | InterlockingError::InvalidSignal(id)
+            | InterlockingError::SameStartEnd(id)
+            | InterlockingError::FilamentFused(id)
+            | InterlockingError::SignalOpen(id)
+            | InterlockingError::NoRouteFound(id)
+            | InterlockingError::NoAvailablePath(id)
+            | InterlockingError::RouteNotFound(id)
+            | InterlockingError::RouteOccupied(id)
+            | InterlockingError::HostileRoute(id)
+            | InterlockingError::NotPassRoute(id)
+            | InterlockingError::SignalBlocked(id)
+            | InterlockingError::RouteReleasing(id)
+            | InterlockingError::NotHomeSignal(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn button(&self) -> Option<&str> {
+        match self {
+            InterlockingError::UnknownButton(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn turnout(&self) -> Option<TurnoutID> {
+        match self {
+            InterlockingError::UnknownTurnout(id) | InterlockingError::TurnoutBlocked(id) => {
+                Some(*id)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for InterlockingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterlockingError::UnknownNode(id) => write!(f, "unknown node id: {}", id),
+            InterlockingError::UnknownSignal(id) => write!(f, "unknown signal id: {}", id),
+            InterlockingError::UnknownButton(id) => write!(f, "unknown button id: {}", id),
+            InterlockingError::UnknownTurnout(id) => write!(f, "unknown turnout id: {}", id),
+            InterlockingError::InvalidSignal(id) => write!(f, "invalid signal {}", id),
+            InterlockingError::SameStartEnd(_) => write!(f, "start and end cannot be same"),
+            InterlockingError::FilamentFused(id) => {
+                write!(f, "start signal {} filament is fused", id)
+            }
+            InterlockingError::SignalOpen(id) => write!(f, "start signal {} is already open", id),
+            InterlockingError::NoRouteFound(_) => write!(f, "no route found"),
+            InterlockingError::NoAvailablePath(_) => write!(f, "no available route exists"),
+            InterlockingError::NodeNotVacant(id) => {
+                write!(f, "target path is not vacant at node {}", id)
+            }
+            InterlockingError::NodeLocked(id) => {
+                write!(f, "target path is conflicting at node {}", id)
+            }
+            InterlockingError::NodeInUse(id) => write!(f, "target path is mutex at node {}", id),
+            InterlockingError::TurnoutBlocked(id) => {
+                write!(f, "target path has blocked turnout {}", id)
+            }
+            InterlockingError::RouteNotFound(id) => {
+                write!(f, "not find a existed route from {}", id)
+            }
+            InterlockingError::RouteOccupied(id) => {
+                write!(f, "route from {} is occupied by a train", id)
+            }
+            InterlockingError::HostileRoute(id) => write!(f, "hostile route from {} is set", id),
+            InterlockingError::ApproachNotVacant(id) => {
+                write!(f, "approching node {} is not vacant", id)
+            }
+            InterlockingError::IncompleteRoute(_) => write!(f, "not a complete route"),
+            InterlockingError::UnknownStoredRoute(id) => write!(f, "unknown stored route: {}", id),
+            InterlockingError::NotPassRoute(id) => {
+                write!(f, "route from {} is not a pass route", id)
+            }
+            InterlockingError::UnknownTrack(id) => write!(f, "unknown track id: {}", id),
+            InterlockingError::NodeBlocked(id) => write!(f, "node {} is blocked", id),
+            InterlockingError::SignalBlocked(id) => write!(f, "button of signal {} is blocked", id),
+            InterlockingError::InvalidTrain(id) => write!(f, "train {} has no position", id),
+            InterlockingError::RouteReleasing(id) => {
+                write!(f, "route from {} is releasing with delay", id)
+            }
+            InterlockingError::NodeNotLocked(id) => {
+                write!(f, "node {} is not locked by any route", id)
+            }
+            InterlockingError::NotHomeSignal(id) => {
+                write!(f, "signal {} is not a home signal", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InterlockingError {}
+
+//code和出錯的ID放在GraphQL錯誤的extensions中
+#[cfg(feature = "graphql")]
+impl ErrorExtensions for InterlockingError {
+    fn extend(&self) -> Error {
+        Error::new(self.to_string()).extend_with(|_, e| {
+            e.set("code", self.code());
+            if let Some(id) = self.node() {
+                e.set("node", id as i32);
+            }
+            if let Some(id) = self.signal() {
+                e.set("signal", id);
+            }
+            if let Some(id) = self.button() {
+                e.set("button", id);
+            }
+            if let Some(id) = self.turnout() {
+                e.set("turnout", id as i32);
+            }
+        })
+    }
+}
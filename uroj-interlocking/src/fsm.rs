@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+
+use crate::raw_station::*;
+#[cfg(feature = "graphql")]
+use async_graphql::{Enum, SimpleObject};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use strum_macros::*;
+
+use crate::{
+    interlocking::Event,
+    route::{Route, RouteID, RouteKind},
+    storage::StoredRoute,
+    topo::Topo,
+};
+
+//進入新區段後停留的tick數，約三秒
+const DWELL_TICKS: u32 = 3000;
+
+//聯鎖狀態機，同步訪問，不需要加鎖
+pub struct InstanceFSM {
+    pub sgns: HashMap<String, Signal>,
+    pub nodes: HashMap<NodeID, Node>,
+    pub turnouts: HashMap<TurnoutID, Turnout>,
+    pub trains: Vec<Train>,
+    pub routes: Vec<Route>,       //已建立的進路，按建立順序
+    pub route_seq: RouteID,       //最後分配的進路ID
+    pub stored: Vec<StoredRoute>, //存儲的進路，按存儲順序
+    pub emergency: bool,          //緊急停車
+}
+
+impl InstanceFSM {
+    pub fn node(&self, id: NodeID) -> &Node {
+        self.nodes.get(&id).expect(&format!("unknown node: {}", id))
+    }
+
+    pub fn node_mut(&mut self, id: NodeID) -> &mut Node {
+        self.nodes
+            .get_mut(&id)
+            .expect(&format!("unknown node: {}", id))
+    }
+
+    pub fn sgn(&self, id: &str) -> &Signal {
+        self.sgns.get(id).expect(&format!("unknown signal: {}", id))
+    }
+
+    pub fn sgn_mut(&mut self, id: &str) -> &mut Signal {
+        self.sgns
+            .get_mut(id)
+            .expect(&format!("unknown signal: {}", id))
+    }
+
+    pub fn turnout(&self, id: TurnoutID) -> &Turnout {
+        self.turnouts
+            .get(&id)
+            .expect(&format!("unknown turnout: {}", id))
+    }
+
+    pub fn spawn_train(
+        &mut self,
+        node: NodeID,
+        dir: RawDirection,
+        overrun: bool,
+        events: &mut Vec<Event>,
+    ) -> TrainID {
+        let id = self.trains.len() + 1;
+        self.node_mut(node).occupy(events); //列車所在的區段占用
+        let mut train = Train::new(node, id, dir, events);
+        train.overrun = overrun;
+        self.trains.push(train);
+        id
+    }
+
+    pub fn route_by_start(&self, sgn_id: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|r| r.is_active() && r.start_sgn == sgn_id)
+    }
+
+    pub fn get_global_status(&self) -> GlobalStatus {
+        GlobalStatus {
+            nodes: self.nodes.values().map(|n| n.to_update_node()).collect(),
+            signals: self.sgns.values().map(|s| s.to_update_signal()).collect(),
+            trains: self.trains.iter().map(|t| t.to_move_train()).collect(),
+            turnouts: self
+                .turnouts
+                .values()
+                .map(|t| t.to_update_turnout())
+                .collect(),
+            emergency: self.emergency,
+        }
+    }
+}
+
+pub type NodeID = usize;
+//Node 狀態機，沒有耦合信息
+pub struct Node {
+    pub node_id: NodeID,
+    pub used_count: u32, //被征用计数，每次征用则INC，每次作为S扩展集中的点被解除征用则减1，为0则说明未被征用
+    pub state: NodeStatus,
+    pub kind: RawNodeKind,
+    pub once_occ: bool,
+    pub is_lock: bool,
+    pub is_blocked: bool,             //區段封鎖，不能排入進路
+    pub turnouts: Vec<TurnoutID>,     //區段内的道岔
+    pub len: f64,                     //全长
+    pub left_sgn_id: Option<String>,  //两端的防护信号机，只有防护自己的信号机才在这里//點燈時用的
+    pub right_sgn_id: Option<String>, //两端的防护信号机，只有防护自己的信号机才在这里//點燈時用的
+}
+
+impl Node {
+    pub fn unlock(&mut self, events: &mut Vec<Event>) {
+        self.is_lock = false;
+        self.sync_state(events);
+    }
+
+    pub fn lock(&mut self, events: &mut Vec<Event>) {
+        self.is_lock = true;
+        self.sync_state(events);
+    }
+
+    pub fn occupy(&mut self, events: &mut Vec<Event>) {
+        self.state = NodeStatus::Occupied;
+        self.sync_state(events);
+    }
+
+    //注入或排除軌道電路故障
+    pub fn set_fault(&mut self, fault: bool, events: &mut Vec<Event>) {
+        self.state = if fault {
+            NodeStatus::Unexpected
+        } else {
+            NodeStatus::Vacant
+        };
+        self.sync_state(events);
+    }
+
+    pub fn set_blocked(&mut self, blocked: bool, events: &mut Vec<Event>) {
+        self.is_blocked = blocked;
+        self.sync_state(events);
+    }
+
+    fn sync_state(&mut self, events: &mut Vec<Event>) {
+        events.push(Event::UpdateNode(self.to_update_node()));
+    }
+
+    fn to_update_node(&self) -> UpdateNode {
+        UpdateNode {
+            id: self.node_id.clone(),
+            state: if self.is_lock {
+                NodeStatus::Lock
+            } else {
+                self.state
+            },
+            blocked: self.is_blocked,
+        }
+    }
+}
+
+impl From<&RawNode> for Node {
+    fn from(data: &RawNode) -> Self {
+        let (x1, y1) = data.line.0;
+        let (x2, y2) = data.line.1;
+
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let len = (dx * dx + dy * dy).sqrt();
+        Node {
+            node_id: data.id,
+            used_count: 0,
+            state: Default::default(),
+            once_occ: false,
+            is_lock: false,
+            is_blocked: false,
+            turnouts: data.turnout_id.clone(),
+            len: len,
+            left_sgn_id: None,  //先缺省，之後推斷
+            right_sgn_id: None, //先缺省之後推斷
+            kind: data.node_kind.clone(),
+        }
+    }
+}
+
+pub type TurnoutID = usize;
+//道岔，位置隨進路，這裏只記錄封鎖（單鎖）狀態和強扳後的位置
+pub struct Turnout {
+    pub id: TurnoutID,
+    pub node_id: NodeID,
+    pub is_blocked: bool,
+    pub position: TurnoutPosition,
+}
+
+impl Turnout {
+    pub fn to_update_turnout(&self) -> UpdateTurnout {
+        UpdateTurnout {
+            id: self.id,
+            position: self.position,
+            blocked: self.is_blocked,
+        }
+    }
+
+    pub fn throw(&mut self, position: TurnoutPosition, events: &mut Vec<Event>) {
+        if self.position != position {
+            self.position = position;
+            events.push(Event::UpdateTurnout(self.to_update_turnout()));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+pub enum TurnoutPosition {
+    Normal,  //定位
+    Reverse, //反位
+}
+
+impl Default for TurnoutPosition {
+    fn default() -> Self {
+        TurnoutPosition::Normal
+    }
+}
+
+//事实上的动态状态
+//由车辆的位置和预设变量决定，是轨道电路的表征
+// 在毕业设计中使用状态转移图，可以凑字数
+#[derive(Clone, Eq, PartialEq, Display, Serialize, Deserialize, Copy)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+pub enum NodeStatus {
+    Occupied,   //占用，赤
+    Unexpected, //异常，条
+    Vacant,     //空闲，蓝
+    Lock,
+}
+
+impl Default for NodeStatus {
+    fn default() -> Self {
+        NodeStatus::Vacant
+    }
+}
+
+pub struct Signal {
+    pub id: String,
+    pub filament_status: (FilamentStatus, FilamentStatus),
+    pub state: SignalStatus,
+    pub kind: RawSignalKind, //因为逻辑不需要变化
+    pub protect_node_id: NodeID,
+    pub toward_node_id: NodeID,
+    pub dir: Direction, //朝向
+    pub btns: Vec<RawButtonKind>,
+    pub hostile: Vec<String>, //聲明的敵對信號機
+    pub is_blocked: bool,     //按鈕封鎖，不能作爲進路的始終端
+}
+
+impl From<&RawSignal> for Signal {
+    fn from(data: &RawSignal) -> Self {
+        let filament_status = match data.sgn_kind {
+            //调车信号机只有一个灯丝
+            RawSignalKind::ShuntingSignal => (Default::default(), FilamentStatus::None),
+            _ => (Default::default(), Default::default()),
+        };
+
+        let state = match data.sgn_kind {
+            RawSignalKind::ShuntingSignal => SignalStatus::A,
+            _ => SignalStatus::H,
+        };
+
+        Signal {
+            id: data.id.clone(),
+            filament_status: filament_status,
+            state: state,
+            kind: data.sgn_kind,
+            protect_node_id: data.protect_node_id,
+            toward_node_id: data.toward_node_id,
+            dir: Direction::Left, //缺省
+            btns: data.btns.clone(),
+            hostile: data.hostile_sgns.clone(),
+            is_blocked: false,
+        }
+    }
+}
+
+impl Signal {
+    pub fn is_allowed(&self) -> bool {
+        self.state.is_allowed()
+    }
+
+    pub fn to_update_signal(&self) -> UpdateSignal {
+        UpdateSignal {
+            id: self.id.clone(),
+            state: self.state,
+            blocked: self.is_blocked,
+        }
+    }
+
+    pub fn set_blocked(&mut self, blocked: bool, events: &mut Vec<Event>) {
+        self.is_blocked = blocked;
+        events.push(Event::UpdateSignal(self.to_update_signal()));
+    }
+
+    pub fn update(&mut self, state: SignalStatus, events: &mut Vec<Event>) {
+        self.state = state;
+
+        events.push(Event::UpdateSignal(self.to_update_signal()));
+    }
+
+    pub fn protect(&mut self, events: &mut Vec<Event>) {
+        let new_state = match self.kind {
+            RawSignalKind::HomeSignal => SignalStatus::H,
+            RawSignalKind::StartingSignal => SignalStatus::H,
+            RawSignalKind::ShuntingSignal => SignalStatus::A,
+        };
+        self.update(new_state, events);
+    }
+
+    //列車進路始端應有的顯示，ahead是前方第一架同向信號機的顯示
+    //接車進側線固定顯示UU/US，進正線和發車、通過進路按前方信號機連鎖顯示
+    pub fn route_aspect(
+        kind: RouteKind,
+        goal_kind: &RawNodeKind,
+        ahead: Option<SignalStatus>,
+    ) -> Option<SignalStatus> {
+        match (kind, goal_kind) {
+            (RouteKind::Shunt, _) => Some(SignalStatus::B),
+            (RouteKind::Receive, RawNodeKind::Siding) => Some(SignalStatus::UU),
+            (RouteKind::Receive, RawNodeKind::Siding18) => Some(SignalStatus::US),
+            (RouteKind::Receive, RawNodeKind::Normal) => None,
+            //接車進正線，前方沒有信號機則在站内停車
+            (RouteKind::Receive, RawNodeKind::Mainline) => {
+                Some(ahead.map_or(SignalStatus::U, SignalStatus::chained))
+            }
+            //發車、通過進路前方沒有信號機即駛入區間，按區間空閒處理
+            _ => Some(ahead.map_or(SignalStatus::L, SignalStatus::chained)),
+        }
+    }
+
+    //开放列車进路，顯示不變時不重複發送
+    pub fn open_route(&mut self, state: SignalStatus, events: &mut Vec<Event>) {
+        if self.state != state {
+            self.update(state, events);
+        }
+    }
+
+    //完全开放信号
+    pub fn open(&mut self, events: &mut Vec<Event>) {
+        let new_state = match self.kind {
+            RawSignalKind::HomeSignal => SignalStatus::L,
+            RawSignalKind::StartingSignal => SignalStatus::L,
+            RawSignalKind::ShuntingSignal => SignalStatus::B,
+        };
+        self.update(new_state, events);
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Display, EnumString)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum SignalStatus {
+    L,
+    U,
+    H,
+    B,
+    A,
+    UU,
+    LU,
+    LL,
+    US,
+    HB,
+    OFF,
+}
+
+impl SignalStatus {
+    pub fn is_allowed(&self) -> bool {
+        match self {
+            SignalStatus::L
+            | SignalStatus::U
+            | SignalStatus::B
+            | SignalStatus::UU
+            | SignalStatus::LU
+            | SignalStatus::LL
+            | SignalStatus::US
+            | SignalStatus::HB => true,
+            SignalStatus::A | SignalStatus::H | SignalStatus::OFF => false,
+        }
+    }
+
+    //四顯示自動閉塞，由前方信號機的顯示推出本信號機的顯示：
+    //前方關閉（H/A/OFF）或顯示引導、調車 -> U，前方空閒一個分區
+    //前方U/UU/US，要求減速或側向 -> LU，前方空閒兩個分區
+    //前方LU/LL -> L，前方空閒三個分區
+    //前方L -> LL，前方空閒四個及以上分區
+    pub fn chained(ahead: SignalStatus) -> SignalStatus {
+        match ahead {
+            SignalStatus::L => SignalStatus::LL,
+            SignalStatus::LU | SignalStatus::LL => SignalStatus::L,
+            SignalStatus::U | SignalStatus::UU | SignalStatus::US => SignalStatus::LU,
+            SignalStatus::H
+            | SignalStatus::A
+            | SignalStatus::OFF
+            | SignalStatus::B
+            | SignalStatus::HB => SignalStatus::U,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Display, EnumString)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum FilamentStatus {
+    Normal,
+    Fused,
+    None,
+}
+
+impl Default for FilamentStatus {
+    fn default() -> Self {
+        FilamentStatus::Normal
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct GlobalStatus {
+    pub nodes: Vec<UpdateNode>,
+    pub signals: Vec<UpdateSignal>,
+    #[serde(default)]
+    pub trains: Vec<MoveTrain>,
+    #[serde(default)]
+    pub turnouts: Vec<UpdateTurnout>,
+    #[serde(default)]
+    pub emergency: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct UpdateSignal {
+    pub id: String,
+    pub state: SignalStatus,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct UpdateNode {
+    pub id: NodeID,
+    pub state: NodeStatus,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct UpdateTurnout {
+    pub id: TurnoutID,
+    pub position: TurnoutPosition,
+    pub blocked: bool,
+}
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct MoveTrain {
+    pub id: usize,
+    pub node_id: NodeID,
+    pub process: f64,
+    pub dir: RawDirection,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+pub enum AlarmKind {
+    Spad,      //冒進信號
+    Collision, //兩列車進入同一區段
+    Fouling,   //列車越過侵限絕緣，與鄰區段的列車側面衝突
+}
+
+//需要立即提醒玩家的險情
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct Alarm {
+    pub kind: AlarmKind,
+    pub trains: Vec<TrainID>,
+    pub node: NodeID,           //發生險情的區段
+    pub signal: Option<String>, //冒進的信號機
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct EmergencyStop {
+    pub active: bool,
+}
+
+pub type TrainID = usize;
+
+pub struct Train {
+    pub id: TrainID,
+    pub process: f64,
+    pub dir: RawDirection,
+    pub past_node: Vec<NodeID>,
+    pub dwell: u32,    //剩餘的停留tick數
+    pub overrun: bool, //不理會信號，模擬司機冒進或制動失效
+}
+
+impl Train {
+    pub fn new(spawn_at: NodeID, id: TrainID, dir: RawDirection, events: &mut Vec<Event>) -> Self {
+        let train = Train {
+            id: id,
+            process: 0.5,
+            past_node: vec![spawn_at],
+            dir: dir,
+            dwell: 0,
+            overrun: false,
+        };
+
+        train.send_states(events);
+        train
+    }
+
+    pub fn turn_direction(&mut self, dir: RawDirection) {
+        self.dir = dir;
+        self.process = 1. - self.process;
+    }
+
+    pub fn send_states(&self, events: &mut Vec<Event>) {
+        events.push(Event::MoveTrain(self.to_move_train()));
+    }
+
+    pub fn to_move_train(&self) -> MoveTrain {
+        MoveTrain {
+            id: self.id,
+            node_id: self.curr_node(),
+            process: self.process,
+            dir: self.dir,
+        }
+    }
+
+    pub fn curr_node(&self) -> NodeID {
+        self.past_node.last().unwrap().clone()
+    }
+
+    //when node state is changed, call me
+    pub fn can_move_to(&self, target: NodeID, topo: &Topo, fsm: &InstanceFSM) -> bool {
+        let curr = self.curr_node();
+        //鄰接保證物理上車可以移動
+        //行车方向, 这是边，没有则不邻接，物理上不可移动
+        let dir = topo.direction(curr, target);
+        if let None = dir {
+            return false;
+        }
+
+        //若沒有防護信號機則無約束，若有則檢查點亮的信號是否允許進入
+        match self.entry_sgn(target, topo, fsm) {
+            Some(s) => fsm.sgn(s).is_allowed(),
+            None => true,
+        }
+    }
+
+    //進入target時越過的防護信號機
+    fn entry_sgn<'a>(
+        &self,
+        target: NodeID,
+        topo: &Topo,
+        fsm: &'a InstanceFSM,
+    ) -> Option<&'a String> {
+        let target_node = fsm.node(target);
+        match topo.direction(self.curr_node(), target)? {
+            RawDirection::Left => target_node.right_sgn_id.as_ref(),
+            RawDirection::Right => target_node.left_sgn_id.as_ref(),
+        }
+    }
+
+    fn move_to(&mut self, target: NodeID, fsm: &mut InstanceFSM) {
+        let from = self.curr_node();
+        debug!("train move to {}", target);
+
+        //入口防護信號燈
+        fsm.node_mut(target).state = NodeStatus::Occupied; //下一段占用
+        let from = fsm.node_mut(from);
+        from.state = NodeStatus::Vacant; // 上一段出清
+        from.once_occ = true; // 上一段曾占用
+        self.past_node.push(target.clone());
+        self.process = 0.;
+
+        //三點檢查
+        // if  {}
+        self.dwell = DWELL_TICKS;
+    }
+
+    //when node state is changed, call me
+    pub fn try_next_step(
+        &mut self,
+        target: NodeID,
+        fsm: &mut InstanceFSM,
+        topo: &Topo,
+        events: &mut Vec<Event>,
+    ) {
+        if self.dwell > 0 {
+            self.dwell -= 1;
+            return;
+        }
+
+        if self.process < 1. {
+            self.process += 1. / fsm.node(target).len;
+        } else if self.can_move_to(target, topo, fsm) {
+            debug!("test move to {}", target);
+            self.move_to(target, fsm);
+        } else if self.overrun && topo.direction(self.curr_node(), target).is_some() {
+            //冒進關閉的信號機
+            let signal = self.entry_sgn(target, topo, fsm).cloned();
+            debug!("train {} passed signal {:?} at danger", self.id, signal);
+            events.push(Event::Alarm(Alarm {
+                kind: AlarmKind::Spad,
+                trains: vec![self.id],
+                node: target,
+                signal: signal,
+            }));
+            self.move_to(target, fsm);
+        } else {
+            return;
+        }
+
+        self.send_states(events);
+    }
+}
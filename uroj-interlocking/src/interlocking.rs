@@ -0,0 +1,1759 @@
+use std::collections::HashMap;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::InterlockingError;
+use crate::fsm::*;
+use crate::raw_station::{RawButtonKind, RawDirection, RawSignalKind, RawStation};
+use crate::route::{Route, RouteID, RouteKind, RouteStatus};
+use crate::storage::StoredRoute;
+use crate::topo::Topo;
+
+//聯鎖產生的事件，由外層轉發給前端
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Event {
+    UpdateSignal(UpdateSignal),
+    UpdateNode(UpdateNode),
+    MoveTrain(MoveTrain),
+    UpdateRoute(Route),
+    Alarm(Alarm),
+    EmergencyStop(EmergencyStop),
+    UpdateStoredRoutes(Vec<StoredRoute>), //存儲進路的隊列有變化
+    UpdateTurnout(UpdateTurnout),
+}
+
+//總人解的延時，以tick計：列車進路3分鐘，調車進路30秒
+pub const TRAIN_RELEASE_DELAY: u32 = 180_000;
+pub const SHUNT_RELEASE_DELAY: u32 = 30_000;
+
+#[derive(Clone, Debug)]
+pub struct PathBtn {
+    pub id: String, //signal or button id
+    pub kind: RawButtonKind,
+}
+
+//輸入聯鎖的命令
+#[derive(Clone, Debug)]
+pub enum Command {
+    CreateRoute {
+        start: PathBtn,
+        end: PathBtn,
+    },
+    CancelRoute {
+        start: PathBtn,
+    },
+    //不能建立時存儲，條件滿足後自動建立
+    StoreRoute {
+        start: PathBtn,
+        end: PathBtn,
+    },
+    CancelStoredRoute {
+        id: RouteID,
+    },
+    //自動通過進路的開關
+    SetAutoRoute {
+        start: PathBtn,
+        auto: bool,
+    },
+    //鉛封按鈕：總人解、區故解、引導和道岔強扳
+    ManualRelease {
+        start: PathBtn,
+    },
+    FaultRelease {
+        node: NodeID,
+    },
+    Guide {
+        start: String,
+    },
+    ForceTurnout {
+        id: TurnoutID,
+        position: TurnoutPosition,
+    },
+    //封鎖或解封區段、信號機按鈕和整條股道
+    BlockNode {
+        id: NodeID,
+        blocked: bool,
+    },
+    BlockSignal {
+        id: String,
+        blocked: bool,
+    },
+    BlockTrack {
+        track: String,
+        blocked: bool,
+    },
+    //教師注入或排除的故障
+    SetTrackFault {
+        node: NodeID,
+        fault: bool,
+    },
+    SetFilamentFault {
+        sgn: String,
+        fused: bool,
+    },
+    SpawnTrain {
+        at: NodeID,
+        dir: RawDirection,
+        overrun: bool, //不理會信號的列車
+    },
+    Tick, //推進所有列車一步
+    ReleaseEmergency,
+}
+
+//同步的聯鎖核心，命令進、事件出，不依賴時鐘和異步運行時
+pub struct Interlocking {
+    pub fsm: InstanceFSM,
+    pub topo: Topo,
+    pub(crate) events: Vec<Event>,
+}
+
+impl Interlocking {
+    pub fn new(station: &RawStation) -> Result<Self, InterlockingError> {
+        let signals = &station.signals;
+        let nodes = &station.nodes;
+
+        let topo = Topo::new(nodes, signals, &station.independent_btns);
+
+        let mut fsm_sgns: HashMap<String, Signal> =
+            signals.iter().map(|s| (s.id.clone(), s.into())).collect();
+        let mut fsm_nodes: HashMap<NodeID, Node> = nodes.iter().map(|n| (n.id, n.into())).collect();
+
+        //fsm_node的left和right，fsm_sgn的dir缺省，需要推斷
+        for s in signals {
+            let pid = s.protect_node_id;
+            let tid = s.toward_node_id;
+
+            let dir = topo
+                .direction(pid, tid)
+                .or(s.dir.into())
+                .ok_or(InterlockingError::InvalidSignal(s.id.clone()))?;
+
+            let p_node = fsm_nodes
+                .get_mut(&pid)
+                .ok_or(InterlockingError::UnknownNode(pid))?;
+
+            //對信號機進行所屬
+            fsm_sgns.get_mut(&s.id).unwrap().dir = dir;
+            match dir {
+                RawDirection::Left => p_node.left_sgn_id = Some(s.id.clone()),
+                RawDirection::Right => p_node.right_sgn_id = Some(s.id.clone()),
+            }
+        }
+
+        let fsm = InstanceFSM {
+            sgns: fsm_sgns,
+            nodes: fsm_nodes,
+            turnouts: nodes
+                .iter()
+                .flat_map(|n| n.turnout_id.iter().map(move |t| (*t, n.id)))
+                .map(|(id, node_id)| {
+                    let turnout = Turnout {
+                        id: id,
+                        node_id: node_id,
+                        is_blocked: false,
+                        position: TurnoutPosition::Normal,
+                    };
+                    (id, turnout)
+                })
+                .collect(),
+
+            trains: Vec::new(),
+            routes: Vec::new(),
+            route_seq: 0,
+            stored: Vec::new(),
+            emergency: false,
+        };
+
+        Ok(Interlocking {
+            fsm: fsm,
+            topo: topo,
+            events: Vec::new(),
+        })
+    }
+
+    //執行命令，返回期間產生的事件；失敗時局面不變，不產生事件
+    pub fn handle(&mut self, cmd: Command) -> Result<Vec<Event>, InterlockingError> {
+        let result = match cmd {
+            Command::CreateRoute { start, end } => {
+                self.create_path(start, end, false).map(|path| {
+                    debug!("new route {:?}", path);
+                })
+            }
+            Command::CancelRoute { start } => self.cancel_path(start),
+            Command::StoreRoute { start, end } => self.store_path(start, end),
+            Command::CancelStoredRoute { id } => self.cancel_stored(id),
+            Command::SetAutoRoute { start, auto } => self.set_auto_route(start, auto),
+            Command::ManualRelease { start } => self.manual_release(start),
+            Command::FaultRelease { node } => self.fault_release(node),
+            Command::Guide { start } => self.open_guide(&start),
+            Command::ForceTurnout { id, position } => self.force_turnout(id, position),
+            Command::BlockNode { id, blocked } => self.block_node(id, blocked),
+            Command::BlockSignal { id, blocked } => self.block_signal(&id, blocked),
+            Command::BlockTrack { track, blocked } => self.block_track(&track, blocked),
+            Command::SetTrackFault { node, fault } => self.set_track_fault(node, fault),
+            Command::SetFilamentFault { sgn, fused } => self.set_filament_fault(&sgn, fused),
+            Command::SpawnTrain { at, dir, overrun } => self.spawn_train(at, dir, overrun),
+            Command::Tick => {
+                self.tick();
+                Ok(())
+            }
+            Command::ReleaseEmergency => {
+                self.release_emergency();
+                Ok(())
+            }
+        };
+        if result.is_ok() {
+            self.process_stored();
+            self.chain_aspects();
+        }
+        let events = std::mem::take(&mut self.events);
+        result.map(|_| events)
+    }
+
+    pub fn get_global_status(&self) -> GlobalStatus {
+        self.fsm.get_global_status()
+    }
+
+    fn spawn_train(
+        &mut self,
+        at: NodeID,
+        dir: RawDirection,
+        overrun: bool,
+    ) -> Result<(), InterlockingError> {
+        if !self.fsm.nodes.contains_key(&at) {
+            return Err(InterlockingError::UnknownNode(at));
+        }
+        let id = self.fsm.spawn_train(at, dir, overrun, &mut self.events);
+        //放入有車的區段或侵限處同樣是險情
+        self.detect_collisions(&[id]);
+        Ok(())
+    }
+
+    //封鎖只影響之後建立的進路，已鎖閉的進路照常使用
+    fn block_node(&mut self, id: NodeID, blocked: bool) -> Result<(), InterlockingError> {
+        self.fsm
+            .nodes
+            .get_mut(&id)
+            .ok_or(InterlockingError::UnknownNode(id))?
+            .set_blocked(blocked, &mut self.events);
+        Ok(())
+    }
+
+    fn block_signal(&mut self, id: &str, blocked: bool) -> Result<(), InterlockingError> {
+        self.fsm
+            .sgns
+            .get_mut(id)
+            .ok_or(InterlockingError::UnknownSignal(id.to_string()))?
+            .set_blocked(blocked, &mut self.events);
+        Ok(())
+    }
+
+    //股道封鎖：同一軌道電路的全部區段
+    fn block_track(&mut self, track: &str, blocked: bool) -> Result<(), InterlockingError> {
+        let mut nodes: Vec<NodeID> = self
+            .topo
+            .track
+            .iter()
+            .filter(|(_, t)| *t == track)
+            .map(|(n, _)| *n)
+            .collect();
+        if nodes.is_empty() {
+            return Err(InterlockingError::UnknownTrack(track.to_string()));
+        }
+        nodes.sort();
+        for n in nodes {
+            self.fsm.node_mut(n).set_blocked(blocked, &mut self.events);
+        }
+        Ok(())
+    }
+
+    //軌道電路故障，區段顯示占用；有車的區段不能注入或排除
+    fn set_track_fault(&mut self, id: NodeID, fault: bool) -> Result<(), InterlockingError> {
+        let node = self
+            .fsm
+            .nodes
+            .get_mut(&id)
+            .ok_or(InterlockingError::UnknownNode(id))?;
+        if node.state == NodeStatus::Occupied {
+            return Err(InterlockingError::NodeNotVacant(id));
+        }
+        node.set_fault(fault, &mut self.events);
+        Ok(())
+    }
+
+    //主燈絲斷絲，已開放的信號隨即關閉
+    fn set_filament_fault(&mut self, id: &str, fused: bool) -> Result<(), InterlockingError> {
+        known_sgn(&self.fsm, id)?;
+        let sgn = self.fsm.sgn_mut(id);
+        sgn.filament_status.0 = if fused {
+            FilamentStatus::Fused
+        } else {
+            FilamentStatus::Normal
+        };
+        if fused && sgn.is_allowed() {
+            sgn.protect(&mut self.events);
+        }
+        Ok(())
+    }
+
+    //推進所有列車一步，沿已鎖閉的進路運行
+    fn tick(&mut self) {
+        //緊急停車期間列車全部停止
+        if self.fsm.emergency {
+            return;
+        }
+
+        let mut trains = std::mem::take(&mut self.fsm.trains);
+        let mut moved = Vec::new();
+        for train in trains.iter_mut() {
+            let next_node = if train.overrun {
+                //冒進的列車沿原方向運行，不受進路約束
+                let dir = train.dir;
+                next_node(&self.fsm, &self.topo, &train.past_node, &dir).map(|n| (n, dir))
+            } else {
+                next_route_node(&self.fsm, &self.topo, &train.past_node, &RawDirection::Left)
+                    .map(|n| (n, RawDirection::Left))
+                    .or_else(|| {
+                        next_route_node(
+                            &self.fsm,
+                            &self.topo,
+                            &train.past_node,
+                            &RawDirection::Right,
+                        )
+                        .map(|n| (n, RawDirection::Right))
+                    })
+            };
+
+            //找不到次一个结点则停在原地
+            if let Some((node, dir)) = next_node {
+                if train.dir != dir {
+                    train.turn_direction(dir);
+                }
+                let passed = train.past_node.len();
+                train.try_next_step(node, &mut self.fsm, &self.topo, &mut self.events);
+                if train.past_node.len() != passed {
+                    moved.push(train.id);
+                }
+            }
+        }
+        self.fsm.trains = trains;
+        self.detect_collisions(&moved);
+        self.update_routes();
+    }
+
+    //剛進入新區段的列車與其他列車同在一個區段爲衝突，隔侵限絕緣相鄰爲侵限
+    //發現險情即緊急停車，等待教師解除
+    fn detect_collisions(&mut self, moved: &[TrainID]) {
+        let trains = &self.fsm.trains;
+        let others = |id: TrainID, node: NodeID| -> Vec<TrainID> {
+            trains
+                .iter()
+                .filter(|t| t.id != id && t.curr_node() == node)
+                .map(|t| t.id)
+                .collect()
+        };
+
+        let mut alarms: Vec<Alarm> = Vec::new();
+        for id in moved {
+            let node = trains.iter().find(|t| t.id == *id).unwrap().curr_node();
+            let hit = others(*id, node);
+            let found = if !hit.is_empty() {
+                vec![(AlarmKind::Collision, node, hit)]
+            } else {
+                self.topo
+                    .c_graph
+                    .neighbors(node)
+                    .map(|n| (AlarmKind::Fouling, n, others(*id, n)))
+                    .filter(|(_, _, hit)| !hit.is_empty())
+                    .collect()
+            };
+
+            for (kind, node, hit) in found {
+                let mut involved = vec![*id];
+                involved.extend(hit);
+                involved.sort();
+                //同一tick雙方都進入時只報一次
+                if alarms
+                    .iter()
+                    .any(|a| a.kind == kind && a.trains == involved)
+                {
+                    continue;
+                }
+                alarms.push(Alarm {
+                    kind: kind,
+                    trains: involved,
+                    node: node,
+                    signal: None,
+                });
+            }
+        }
+
+        if alarms.is_empty() {
+            return;
+        }
+        for alarm in alarms {
+            debug!(
+                "{:?} of trains {:?} at {}",
+                alarm.kind, alarm.trains, alarm.node
+            );
+            self.events.push(Event::Alarm(alarm));
+        }
+        self.fsm.emergency = true;
+        self.events
+            .push(Event::EmergencyStop(EmergencyStop { active: true }));
+    }
+
+    //解除緊急停車，列車恢復運行
+    fn release_emergency(&mut self) {
+        if self.fsm.emergency {
+            self.fsm.emergency = false;
+            self.events
+                .push(Event::EmergencyStop(EmergencyStop { active: false }));
+        }
+    }
+
+    //パースを作成する
+    pub(crate) fn create_path(
+        &mut self,
+        start: PathBtn,
+        end: PathBtn,
+        auto: bool,
+    ) -> Result<Vec<NodeID>, InterlockingError> {
+        //同一始終端的進路仍在，則是重複開放
+        if let Some(idx) = self
+            .fsm
+            .routes
+            .iter()
+            .position(|r| r.is_active() && r.start_sgn == start.id && r.end_id == end.id)
+        {
+            return self.reopen_path(idx);
+        }
+
+        let plan = self.check_route(&start, &end)?;
+        if let Some(e) = plan.failure() {
+            debug!("route from {} rejected: {}", start.id, e);
+            return Err(e);
+        }
+
+        let aspect = self.route_aspect(plan.kind, &plan.path, &start.id);
+
+        let topo = &self.topo;
+        let events = &mut self.events;
+        let fsm = &mut self.fsm;
+
+        //锁闭区段，記下路徑上同向的信號機
+        let mut sgn_id = Vec::new();
+        let mut reserved = Vec::new();
+        for id in &plan.path {
+            let node = fsm.node_mut(*id);
+            debug!("trying to lock: {}", id);
+            node.lock(events);
+            node.once_occ = false; //重置曾占用flag
+
+            let sgn = match plan.dir {
+                RawDirection::Left => node.right_sgn_id.clone(),
+                RawDirection::Right => node.left_sgn_id.clone(),
+            };
+            if let Some(sgn) = sgn {
+                sgn_id.push(sgn);
+            }
+
+            for id in topo.s_graph.neighbors(*id) {
+                let node = fsm.node_mut(id);
+                node.used_count += 1;
+                reserved.push(id);
+                debug!("{}.used_count= {}", id, node.used_count);
+            }
+        }
+
+        let mut signals = vec![start.id.clone()];
+        match plan.kind {
+            RouteKind::Receive | RouteKind::Pass | RouteKind::Depart => {
+                if let Some(aspect) = aspect {
+                    fsm.sgn_mut(&start.id).open_route(aspect, events);
+                }
+            }
+            RouteKind::Shunt => {
+                fsm.sgn_mut(&start.id).open(events);
+
+                for id in sgn_id {
+                    if id == start.id {
+                        continue;
+                    }
+                    fsm.sgn_mut(&id).open(events);
+                    signals.push(id);
+                }
+            }
+        }
+
+        //登記進路
+        fsm.route_seq += 1;
+        let route = Route {
+            id: fsm.route_seq,
+            kind: plan.kind,
+            state: if fsm.sgn(&start.id).is_allowed() {
+                RouteStatus::Opened
+            } else {
+                RouteStatus::Set
+            },
+            start_sgn: start.id,
+            start_btn: start.kind,
+            end_id: end.id,
+            end_btn: end.kind,
+            nodes: plan.path.clone(),
+            reserved: reserved,
+            signals: signals,
+            released: vec![],
+            auto: auto,
+            release_delay: 0,
+        };
+        events.push(Event::UpdateRoute(route.clone()));
+        fsm.routes.push(route);
+
+        Ok(plan.path)
+    }
+
+    //重複開放：信號因故關閉後，進路仍完整鎖閉且列車未進入時，可再次開放
+    fn reopen_path(&mut self, idx: usize) -> Result<Vec<NodeID>, InterlockingError> {
+        let route = &self.fsm.routes[idx];
+        let start_sgn = self.fsm.sgn(&route.start_sgn);
+        if start_sgn.is_allowed() {
+            return Err(InterlockingError::SignalOpen(route.start_sgn.clone()));
+        }
+        if route.state == RouteStatus::Releasing {
+            return Err(InterlockingError::RouteReleasing(route.start_sgn.clone()));
+        }
+        if route.state != RouteStatus::Set || !route.released.is_empty() {
+            return Err(InterlockingError::RouteOccupied(route.start_sgn.clone()));
+        }
+        if start_sgn.filament_status.0 == FilamentStatus::Fused {
+            return Err(InterlockingError::FilamentFused(route.start_sgn.clone()));
+        }
+        //與建立進路一樣，封鎖的按鈕和區段不能重複開放
+        if start_sgn.is_blocked {
+            return Err(InterlockingError::SignalBlocked(route.start_sgn.clone()));
+        }
+        if let Some(end_sgn) = self.fsm.sgns.get(&route.end_id) {
+            if end_sgn.is_blocked {
+                return Err(InterlockingError::SignalBlocked(route.end_id.clone()));
+            }
+        }
+        for id in &route.nodes {
+            let node = self.fsm.node(*id);
+            if node.state != NodeStatus::Vacant || node.once_occ {
+                return Err(InterlockingError::NodeNotVacant(*id));
+            }
+            if node.is_blocked {
+                return Err(InterlockingError::NodeBlocked(*id));
+            }
+            for t in &node.turnouts {
+                if self.fsm.turnout(*t).is_blocked {
+                    return Err(InterlockingError::TurnoutBlocked(*t));
+                }
+            }
+        }
+
+        let aspect = self.route_aspect(route.kind, &route.nodes, &route.start_sgn);
+        let mut route = self.fsm.routes[idx].clone();
+        let events = &mut self.events;
+        let fsm = &mut self.fsm;
+        match route.kind {
+            RouteKind::Shunt => {
+                for id in &route.signals {
+                    fsm.sgn_mut(id).open(events);
+                }
+            }
+            _ => {
+                if let Some(aspect) = aspect {
+                    fsm.sgn_mut(&route.start_sgn).open_route(aspect, events);
+                }
+            }
+        }
+
+        if fsm.sgn(&route.start_sgn).is_allowed() {
+            route.state = RouteStatus::Opened;
+            events.push(Event::UpdateRoute(route.clone()));
+        }
+        let nodes = route.nodes.clone();
+        fsm.routes[idx] = route;
+        Ok(nodes)
+    }
+
+    //自動通過進路：列車通過、進路全部解鎖後自動重新建立並開放信號
+    fn set_auto_route(&mut self, start: PathBtn, auto: bool) -> Result<(), InterlockingError> {
+        let route = self
+            .fsm
+            .routes
+            .iter_mut()
+            .find(|r| r.is_active() && r.start_sgn == start.id)
+            .ok_or(InterlockingError::RouteNotFound(start.id.clone()))?;
+        if route.kind != RouteKind::Pass {
+            return Err(InterlockingError::NotPassRoute(start.id));
+        }
+
+        if route.auto != auto {
+            route.auto = auto;
+            self.events.push(Event::UpdateRoute(route.clone()));
+        }
+        Ok(())
+    }
+
+    //重新建立自動通過進路，暫時不能建立則存儲，條件滿足後再建立
+    fn renew_auto_route(&mut self, start: PathBtn, end: PathBtn) {
+        if let Err(e) = self.create_path(start.clone(), end.clone(), true) {
+            debug!("auto route from {} is waiting: {}", start.id, e);
+            self.store_blocked(start, end, e, true);
+        }
+    }
+
+    //進路を消す
+    fn cancel_path(&mut self, start: PathBtn) -> Result<(), InterlockingError> {
+        let start_sgn = known_sgn(&self.fsm, &start.id)?;
+        let toward_node = start_sgn.toward_node_id;
+
+        let idx = self
+            .fsm
+            .routes
+            .iter()
+            .position(|r| r.is_active() && r.start_sgn == start.id)
+            .ok_or(InterlockingError::RouteNotFound(start.id.clone()))?;
+        if self.fsm.routes[idx].state == RouteStatus::Releasing {
+            return Err(InterlockingError::RouteReleasing(start.id));
+        }
+        if !self.fsm.routes[idx].can_cancel() {
+            return Err(InterlockingError::RouteOccupied(start.id));
+        }
+
+        let close_node = self.fsm.node(toward_node);
+        debug!("接近区段: {}", close_node.node_id);
+
+        if close_node.state != NodeStatus::Vacant {
+            return Err(InterlockingError::ApproachNotVacant(toward_node));
+        }
+        if close_node.is_lock {
+            return Err(InterlockingError::IncompleteRoute(toward_node));
+        }
+
+        self.release_route(idx);
+        Ok(())
+    }
+
+    //立即解鎖整條進路並關閉信號
+    fn release_route(&mut self, idx: usize) {
+        let mut route = self.fsm.routes.remove(idx);
+        let events = &mut self.events;
+        let fsm = &mut self.fsm;
+
+        //鎖閉始端信號機，調車進路鎖閉所有隨進路開放的信號機
+        for id in &route.signals {
+            fsm.sgn_mut(id).protect(events);
+        }
+
+        //解鎖所有節點
+        for n in route.nodes.clone() {
+            if !route.released.contains(&n) {
+                release_node(fsm, &self.topo, &mut route, n, events);
+            }
+        }
+        route.state = RouteStatus::Released;
+        events.push(Event::UpdateRoute(route));
+    }
+
+    //總人解：列車未進入的進路，接近區段有車或仍鎖閉時關閉信號並延時解鎖，否則立即解鎖
+    fn manual_release(&mut self, start: PathBtn) -> Result<(), InterlockingError> {
+        let toward_node = known_sgn(&self.fsm, &start.id)?.toward_node_id;
+        let idx = self
+            .fsm
+            .routes
+            .iter()
+            .position(|r| r.is_active() && r.start_sgn == start.id)
+            .ok_or(InterlockingError::RouteNotFound(start.id.clone()))?;
+        if self.fsm.routes[idx].state == RouteStatus::Releasing {
+            return Err(InterlockingError::RouteReleasing(start.id));
+        }
+        //列車已進入的進路逐段解鎖，故障時用區故解
+        if !self.fsm.routes[idx].can_cancel() {
+            return Err(InterlockingError::RouteOccupied(start.id));
+        }
+
+        let close_node = self.fsm.node(toward_node);
+        if close_node.state == NodeStatus::Vacant && !close_node.is_lock {
+            self.release_route(idx);
+            return Ok(());
+        }
+
+        let events = &mut self.events;
+        let fsm = &mut self.fsm;
+        let mut route = fsm.routes[idx].clone();
+        for id in &route.signals {
+            fsm.sgn_mut(id).protect(events);
+        }
+        route.state = RouteStatus::Releasing;
+        route.release_delay = match route.kind {
+            RouteKind::Shunt => SHUNT_RELEASE_DELAY,
+            _ => TRAIN_RELEASE_DELAY,
+        };
+        route.auto = false; //人工解鎖後不再自動重建
+        events.push(Event::UpdateRoute(route.clone()));
+        fsm.routes[idx] = route;
+        Ok(())
+    }
+
+    //區故解：軌道電路故障使區段未能隨列車解鎖時，人工解鎖一個空閒的區段
+    fn fault_release(&mut self, node: NodeID) -> Result<(), InterlockingError> {
+        let target = self
+            .fsm
+            .nodes
+            .get(&node)
+            .ok_or(InterlockingError::UnknownNode(node))?;
+        let idx = self
+            .fsm
+            .routes
+            .iter()
+            .position(|r| r.is_active() && r.nodes.contains(&node) && !r.released.contains(&node))
+            .ok_or(InterlockingError::NodeNotLocked(node))?;
+        if target.state != NodeStatus::Vacant {
+            return Err(InterlockingError::NodeNotVacant(node));
+        }
+        let route = &self.fsm.routes[idx];
+        if self.fsm.sgn(&route.start_sgn).is_allowed() {
+            return Err(InterlockingError::SignalOpen(route.start_sgn.clone()));
+        }
+
+        let mut route = self.fsm.routes.remove(idx);
+        let events = &mut self.events;
+        let fsm = &mut self.fsm;
+        release_node(fsm, &self.topo, &mut route, node, events);
+        if route.released.len() == route.nodes.len() {
+            for id in &route.signals {
+                if fsm.sgn(id).is_allowed() {
+                    fsm.sgn_mut(id).protect(events);
+                }
+            }
+            route.state = RouteStatus::Released;
+        } else if route.state != RouteStatus::Releasing {
+            route.state = RouteStatus::PartiallyReleased;
+        }
+        events.push(Event::UpdateRoute(route.clone()));
+        if route.is_active() {
+            fsm.routes.insert(idx, route);
+        }
+        Ok(())
+    }
+
+    //道岔強扳：區段軌道電路故障時強行轉換道岔，鎖閉、單鎖和列車壓岔時仍然拒絕
+    fn force_turnout(
+        &mut self,
+        id: TurnoutID,
+        position: TurnoutPosition,
+    ) -> Result<(), InterlockingError> {
+        let turnout = self
+            .fsm
+            .turnouts
+            .get(&id)
+            .ok_or(InterlockingError::UnknownTurnout(id))?;
+        if turnout.is_blocked {
+            return Err(InterlockingError::TurnoutBlocked(id));
+        }
+        let node = self.fsm.node(turnout.node_id);
+        if node.is_lock {
+            return Err(InterlockingError::NodeLocked(node.node_id));
+        }
+        if node.state == NodeStatus::Occupied {
+            return Err(InterlockingError::NodeNotVacant(node.node_id));
+        }
+
+        self.fsm
+            .turnouts
+            .get_mut(&id)
+            .unwrap()
+            .throw(position, &mut self.events);
+        Ok(())
+    }
+
+    //引導：進路已鎖閉但信號不能正常開放時，進站信號機開放引導信號，列車進入第一區段後關閉
+    fn open_guide(&mut self, start: &str) -> Result<(), InterlockingError> {
+        let sgn = known_sgn(&self.fsm, start)?;
+        if sgn.kind != RawSignalKind::HomeSignal {
+            return Err(InterlockingError::NotHomeSignal(start.to_string()));
+        }
+        if sgn.is_blocked {
+            return Err(InterlockingError::SignalBlocked(start.to_string()));
+        }
+        if sgn.is_allowed() {
+            return Err(InterlockingError::SignalOpen(start.to_string()));
+        }
+        let idx = self
+            .fsm
+            .routes
+            .iter()
+            .position(|r| r.is_active() && r.start_sgn == start)
+            .ok_or(InterlockingError::RouteNotFound(start.to_string()))?;
+        match self.fsm.routes[idx].state {
+            RouteStatus::Set => {}
+            RouteStatus::Releasing => {
+                return Err(InterlockingError::RouteReleasing(start.to_string()))
+            }
+            _ => return Err(InterlockingError::RouteOccupied(start.to_string())),
+        }
+
+        let route = &mut self.fsm.routes[idx];
+        route.state = RouteStatus::Opened;
+        let route = route.clone();
+        self.fsm
+            .sgn_mut(start)
+            .open_route(SignalStatus::HB, &mut self.events);
+        self.events.push(Event::UpdateRoute(route));
+        Ok(())
+    }
+
+    //列車進路始端信號機應有的顯示
+    fn route_aspect(
+        &self,
+        kind: RouteKind,
+        path: &[NodeID],
+        start_sgn: &str,
+    ) -> Option<SignalStatus> {
+        let dir = self.fsm.sgn(start_sgn).dir.reverse();
+        let goal_kind = &self.fsm.node(*path.last()?).kind;
+        let ahead = self.signal_ahead(path, dir).map(|s| s.state);
+        Signal::route_aspect(kind, goal_kind, ahead)
+    }
+
+    //進路終端前方第一架同向信號機，遇到分岔時只沿已鎖閉的區段找
+    fn signal_ahead(&self, path: &[NodeID], dir: RawDirection) -> Option<&Signal> {
+        let mut his = path.to_vec();
+        loop {
+            let curr = *his.last()?;
+            let next: Vec<NodeID> = self
+                .topo
+                .r_graph
+                .edges(curr)
+                .filter(|(_, t, d)| **d == dir && !his.contains(t))
+                .map(|(_, t, _)| t)
+                .collect();
+            let next = match next.as_slice() {
+                [t] => *t,
+                _ => next.into_iter().find(|t| self.fsm.node(*t).is_lock)?,
+            };
+
+            let node = self.fsm.node(next);
+            let sgn = match dir {
+                RawDirection::Left => node.right_sgn_id.as_ref(),
+                RawDirection::Right => node.left_sgn_id.as_ref(),
+            };
+            if let Some(id) = sgn {
+                return Some(self.fsm.sgn(id));
+            }
+            his.push(next);
+        }
+    }
+
+    //前方信號機變化後，重新推出已開放的列車進路始端的顯示，逐級向後傳遞直到不再變化
+    fn chain_aspects(&mut self) {
+        for _ in 0..=self.fsm.routes.len() {
+            let changed: Vec<(String, SignalStatus)> = self
+                .fsm
+                .routes
+                .iter()
+                .filter(|r| r.is_active() && r.kind != RouteKind::Shunt)
+                .filter(|r| {
+                    let sgn = self.fsm.sgn(&r.start_sgn);
+                    sgn.is_allowed() && sgn.state != SignalStatus::HB
+                })
+                .filter_map(|r| {
+                    let aspect = self.route_aspect(r.kind, &r.nodes, &r.start_sgn)?;
+                    if aspect != self.fsm.sgn(&r.start_sgn).state {
+                        Some((r.start_sgn.clone(), aspect))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            if changed.is_empty() {
+                return;
+            }
+            for (id, aspect) in changed {
+                self.fsm.sgn_mut(&id).open_route(aspect, &mut self.events);
+            }
+        }
+    }
+
+    //列車駛過後逐段解鎖：區段曾占用且已出清即解鎖，並解除其征用
+    fn update_routes(&mut self) {
+        let mut routes = std::mem::take(&mut self.fsm.routes);
+        let mut renew = Vec::new();
+        for route in routes.iter_mut() {
+            let before = (route.state, route.released.len());
+            let fsm = &mut self.fsm;
+
+            //信號機內方第一區段有車即自動關閉，直到重複開放或再次建立進路
+            //引導信號只在列車進入後關閉，不受軌道電路故障影響
+            for id in &route.signals {
+                let sgn = fsm.sgn(id);
+                let state = fsm.node(sgn.protect_node_id).state;
+                let close = match sgn.state {
+                    SignalStatus::HB => state == NodeStatus::Occupied,
+                    _ => state != NodeStatus::Vacant,
+                };
+                if sgn.is_allowed() && close {
+                    fsm.sgn_mut(id).protect(&mut self.events);
+                }
+            }
+            if route.state == RouteStatus::Opened && !fsm.sgn(&route.start_sgn).is_allowed() {
+                route.state = RouteStatus::Set;
+            }
+
+            if route
+                .nodes
+                .iter()
+                .any(|n| fsm.node(*n).state == NodeStatus::Occupied)
+                && route.can_cancel()
+            {
+                route.state = RouteStatus::Occupied;
+            }
+
+            for n in route.nodes.clone() {
+                let node = fsm.node(n);
+                if !route.released.contains(&n) && node.once_occ && node.state == NodeStatus::Vacant
+                {
+                    release_node(fsm, &self.topo, route, n, &mut self.events);
+                }
+            }
+
+            //總人解延時結束，解鎖剩餘區段
+            if route.state == RouteStatus::Releasing {
+                route.release_delay = route.release_delay.saturating_sub(1);
+                if route.release_delay == 0 {
+                    for n in route.nodes.clone() {
+                        if !route.released.contains(&n) {
+                            release_node(fsm, &self.topo, route, n, &mut self.events);
+                        }
+                    }
+                }
+            }
+
+            if route.released.len() == route.nodes.len() {
+                //全部解鎖後關閉仍開放的信號機
+                for id in &route.signals {
+                    if fsm.sgn(id).is_allowed() {
+                        fsm.sgn_mut(id).protect(&mut self.events);
+                    }
+                }
+                route.state = RouteStatus::Released;
+                if route.auto {
+                    renew.push(route.buttons());
+                }
+            } else if !route.released.is_empty() && route.state != RouteStatus::Releasing {
+                route.state = RouteStatus::PartiallyReleased;
+            }
+
+            if (route.state, route.released.len()) != before {
+                self.events.push(Event::UpdateRoute(route.clone()));
+            }
+        }
+        routes.retain(|r| r.is_active());
+        self.fsm.routes = routes;
+
+        for (start, end) in renew {
+            self.renew_auto_route(start, end);
+        }
+    }
+}
+
+//解鎖進路中的一個區段，对扩展集中的点解除征用
+fn release_node(
+    fsm: &mut InstanceFSM,
+    topo: &Topo,
+    route: &mut Route,
+    n: NodeID,
+    events: &mut Vec<Event>,
+) {
+    fsm.node_mut(n).unlock(events);
+    for id in topo.s_graph.neighbors(n) {
+        fsm.node_mut(id).used_count -= 1;
+    }
+    route.released.push(n);
+}
+
+//用户輸入的信號機ID，不存在時報錯而不是panic
+fn known_sgn<'a>(fsm: &'a InstanceFSM, id: &str) -> Result<&'a Signal, InterlockingError> {
+    fsm.sgns
+        .get(id)
+        .ok_or(InterlockingError::UnknownSignal(id.to_string()))
+}
+
+//不論是否鎖閉的次一區段，有已鎖閉的則優先沿進路走
+pub fn next_node(
+    fsm: &InstanceFSM,
+    topo: &Topo,
+    his: &Vec<NodeID>,
+    dir: &RawDirection,
+) -> Option<NodeID> {
+    next_route_node(fsm, topo, his, dir).or_else(|| {
+        topo.r_graph
+            .edges(*his.last().unwrap())
+            .find(|(_, t, d)| *d == dir && !his.contains(t))
+            .map(|(_, t, _)| t)
+    })
+}
+
+pub fn next_route_node(
+    fsm: &InstanceFSM,
+    topo: &Topo,
+    his: &Vec<NodeID>,
+    dir: &RawDirection,
+) -> Option<NodeID> {
+    let edges = topo.r_graph.edges(*his.last().unwrap());
+
+    for (_, t, d) in edges {
+        if d == dir && !his.contains(&t) {
+            let to = fsm.node(t);
+            if to.is_lock && to.state == NodeStatus::Vacant {
+                return Some(t);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::raw_station::RawJointKind;
+
+    //1-2-3-4-5-6 一字排開，3和4同屬股道3G
+    //左端 X 防護 2 朝向 1，S_L 防護 2 朝向 3；右端 XF 防護 5 朝向 6，SF 防護 5 朝向 4
+    pub(crate) fn station() -> RawStation {
+        let nodes: Vec<String> = (1..=6)
+            .map(|i| {
+                let left: Vec<usize> = if i > 1 { vec![i - 1] } else { vec![] };
+                let right: Vec<usize> = if i < 6 { vec![i + 1] } else { vec![] };
+                let x = (i - 1) as f64 * 2.;
+                format!(
+                    r#"{{"id":{},"node_kind":"{}","turnout_id":[],"track_id":"{}G",
+                    "left_adj":{:?},"right_adj":{:?},"conflicted_nodes":[],
+                    "line":[[{},0],[{},0]],"joint":["NORMAL","NORMAL"]}}"#,
+                    i,
+                    if i == 3 || i == 4 {
+                        "MAINLINE"
+                    } else {
+                        "NORMAL"
+                    },
+                    if i == 4 { 3 } else { i },
+                    left,
+                    right,
+                    x,
+                    x + 2.
+                )
+            })
+            .collect();
+        let signal = |id: &str, kind: &str, protect: usize, toward: usize| {
+            format!(
+                r#"{{"id":"{}","side":"UPPER","sgn_kind":"{}","sgn_mnt":"POST_MOUNTING",
+                "protect_node_id":{},"toward_node_id":{},"btns":["TRAIN"]}}"#,
+                id, kind, protect, toward
+            )
+        };
+        let json = format!(
+            r#"{{"title":"test","nodes":[{}],"signals":[{},{},{},{}],"independent_btns":[]}}"#,
+            nodes.join(","),
+            signal("X", "HOME_SIGNAL", 2, 1),
+            signal("S_L", "STARTING_SIGNAL", 2, 3),
+            signal("XF", "HOME_SIGNAL", 5, 6),
+            signal("SF", "STARTING_SIGNAL", 5, 4),
+        );
+        RawStation::from_json(&json).unwrap()
+    }
+
+    pub(crate) fn train_btn(id: &str) -> PathBtn {
+        PathBtn {
+            id: id.to_string(),
+            kind: RawButtonKind::Train,
+        }
+    }
+
+    pub(crate) fn receive_route() -> Command {
+        Command::CreateRoute {
+            start: train_btn("X"),
+            end: train_btn("S_L"),
+        }
+    }
+
+    #[test]
+    fn test_create_receive_route() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        let events = il.handle(receive_route()).unwrap();
+
+        assert!(il.fsm.node(2).is_lock);
+        assert!(il.fsm.node(3).is_lock);
+        assert!(!il.fsm.node(4).is_lock);
+        assert!(il.fsm.sgn("X").state == SignalStatus::U);
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateSignal(s) => s.id == "X" && s.state == SignalStatus::U,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_aspect_follows_signal_ahead() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        //前方出站信號機SF關閉，X顯示U
+        assert!(il.fsm.sgn("X").state == SignalStatus::U);
+
+        il.fsm.sgn_mut("SF").update(SignalStatus::U, &mut vec![]);
+        let events = il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::LU);
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateSignal(s) => s.id == "X" && s.state == SignalStatus::LU,
+            _ => false,
+        }));
+
+        il.fsm.sgn_mut("SF").update(SignalStatus::L, &mut vec![]);
+        il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::LL);
+
+        il.fsm.sgn_mut("SF").protect(&mut vec![]);
+        il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::U);
+    }
+
+    #[test]
+    fn test_green_green_aspect() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+
+        //前方綠燈顯示綠綠，前方綠黃或綠綠退爲綠燈
+        for (ahead, expected) in vec![
+            (SignalStatus::L, SignalStatus::LL),
+            (SignalStatus::LU, SignalStatus::L),
+            (SignalStatus::L, SignalStatus::LL),
+            (SignalStatus::LL, SignalStatus::L),
+        ] {
+            il.fsm.sgn_mut("SF").update(ahead, &mut vec![]);
+            let events = il.handle(Command::Tick).unwrap();
+            assert!(il.fsm.sgn("X").state == expected);
+            assert!(events.iter().any(|e| match e {
+                Event::UpdateSignal(s) => s.id == "X" && s.state == expected,
+                _ => false,
+            }));
+        }
+    }
+
+    //從右端接入同一股道，迎面敵對
+    fn opposite_receive_route() -> Command {
+        Command::CreateRoute {
+            start: train_btn("XF"),
+            end: train_btn("SF"),
+        }
+    }
+
+    #[test]
+    fn test_head_on_route_rejected() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+
+        let result = il.handle(opposite_receive_route());
+        assert_eq!(
+            result.err(),
+            Some(InterlockingError::HostileRoute("X".to_string()))
+        );
+        assert!(!il.fsm.node(4).is_lock);
+        assert!(il.fsm.sgn("XF").state == SignalStatus::H);
+    }
+
+    #[test]
+    fn test_head_on_route_after_cancel() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        il.handle(Command::CancelRoute {
+            start: train_btn("X"),
+        })
+        .unwrap();
+
+        il.handle(opposite_receive_route()).unwrap();
+        assert!(il.fsm.node(4).is_lock);
+        assert!(il.fsm.node(5).is_lock);
+    }
+
+    #[test]
+    fn test_conflicting_route_rejected() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+
+        let result = il.handle(receive_route());
+        assert_eq!(
+            result.err(),
+            Some(InterlockingError::SignalOpen("X".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_cancel_route() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        il.handle(Command::CancelRoute {
+            start: train_btn("X"),
+        })
+        .unwrap();
+
+        assert!(!il.fsm.node(2).is_lock);
+        assert!(!il.fsm.node(3).is_lock);
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+        assert!(il.fsm.routes.is_empty());
+    }
+
+    #[test]
+    fn test_train_enters_route() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(Command::SpawnTrain {
+            at: 1,
+            dir: RawDirection::Right,
+            overrun: false,
+        })
+        .unwrap();
+        il.handle(receive_route()).unwrap();
+
+        for _ in 0..2 {
+            il.handle(Command::Tick).unwrap();
+        }
+
+        assert_eq!(il.fsm.trains[0].curr_node(), 2);
+        assert!(il.fsm.node(2).state == NodeStatus::Occupied);
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::Occupied);
+        //列車越過後自動關閉
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+
+        //列車已進入，不能重複開放
+        let result = il.handle(receive_route());
+        assert_eq!(
+            result.err(),
+            Some(InterlockingError::RouteOccupied("X".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_reopen_after_fault_close() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+
+        //軌道電路瞬間故障，信號關閉
+        il.fsm.node_mut(2).state = NodeStatus::Unexpected;
+        il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::Set);
+
+        //故障未恢復時不能開放
+        let result = il.handle(receive_route());
+        assert_eq!(result.err(), Some(InterlockingError::NodeNotVacant(2)));
+
+        il.fsm.node_mut(2).state = NodeStatus::Vacant;
+        il.handle(receive_route()).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::U);
+        assert_eq!(il.fsm.routes.len(), 1);
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::Opened);
+    }
+
+    #[test]
+    fn test_reopen_rejects_blocked_elements() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        il.fsm.node_mut(2).state = NodeStatus::Unexpected;
+        il.handle(Command::Tick).unwrap();
+        il.fsm.node_mut(2).state = NodeStatus::Vacant;
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::Set);
+
+        let block_node = |blocked| Command::BlockNode {
+            id: 3,
+            blocked: blocked,
+        };
+        il.handle(block_node(true)).unwrap();
+        assert_eq!(
+            il.handle(receive_route()).err(),
+            Some(InterlockingError::NodeBlocked(3))
+        );
+        il.handle(block_node(false)).unwrap();
+
+        il.handle(Command::BlockSignal {
+            id: "X".to_string(),
+            blocked: true,
+        })
+        .unwrap();
+        assert_eq!(
+            il.handle(receive_route()).err(),
+            Some(InterlockingError::SignalBlocked("X".to_string()))
+        );
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+    }
+
+    #[test]
+    fn test_spad_raises_alarm() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(Command::SpawnTrain {
+            at: 1,
+            dir: RawDirection::Right,
+            overrun: true,
+        })
+        .unwrap();
+
+        //X未開放，列車冒進駛入2
+        let mut alarms = vec![];
+        for _ in 0..4 {
+            for e in il.handle(Command::Tick).unwrap() {
+                if let Event::Alarm(a) = e {
+                    alarms.push(a);
+                }
+            }
+        }
+
+        assert_eq!(il.fsm.trains[0].curr_node(), 2);
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].kind, AlarmKind::Spad);
+        assert_eq!(alarms[0].signal.as_deref(), Some("X"));
+    }
+
+    fn spawn(il: &mut Interlocking, at: NodeID, overrun: bool) {
+        il.handle(Command::SpawnTrain {
+            at: at,
+            dir: RawDirection::Right,
+            overrun: overrun,
+        })
+        .unwrap();
+    }
+
+    fn alarms_within(il: &mut Interlocking, ticks: usize) -> Vec<Alarm> {
+        let mut alarms = vec![];
+        for _ in 0..ticks {
+            for e in il.handle(Command::Tick).unwrap() {
+                if let Event::Alarm(a) = e {
+                    alarms.push(a);
+                }
+            }
+        }
+        alarms
+    }
+
+    #[test]
+    fn test_collision_stops_all_trains() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        spawn(&mut il, 1, true);
+        spawn(&mut il, 2, false);
+
+        let alarms = alarms_within(&mut il, 4);
+        assert!(alarms
+            .iter()
+            .any(|a| a.kind == AlarmKind::Collision && a.trains == vec![1, 2] && a.node == 2));
+        assert!(il.fsm.emergency);
+
+        //緊急停車期間不再運行
+        assert!(il.handle(Command::Tick).unwrap().is_empty());
+
+        let events = il.handle(Command::ReleaseEmergency).unwrap();
+        assert!(!il.fsm.emergency);
+        assert!(events.iter().any(|e| match e {
+            Event::EmergencyStop(s) => !s.active,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_spawn_into_occupied_section() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        spawn(&mut il, 2, false);
+        let events = il
+            .handle(Command::SpawnTrain {
+                at: 2,
+                dir: RawDirection::Left,
+                overrun: false,
+            })
+            .unwrap();
+
+        assert!(events.iter().any(|e| match e {
+            Event::Alarm(a) => a.kind == AlarmKind::Collision && a.trains == vec![1, 2],
+            _ => false,
+        }));
+        assert!(il.fsm.emergency);
+    }
+
+    #[test]
+    fn test_spad_after_spawn_at_closed_signal() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        spawn(&mut il, 1, true);
+        assert!(il.fsm.node(1).state == NodeStatus::Occupied);
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+
+        let alarms = alarms_within(&mut il, 4);
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].kind, AlarmKind::Spad);
+        assert_eq!(alarms[0].node, 2);
+        assert!(il.fsm.node(1).state == NodeStatus::Vacant);
+        assert!(il.fsm.node(2).state == NodeStatus::Occupied);
+    }
+
+    #[test]
+    fn test_fouling_clearance_joint() {
+        let mut station = station();
+        //2的右端是侵限絕緣
+        station.nodes[1].joint.1 = RawJointKind::Clearance;
+        let mut il = Interlocking::new(&station).unwrap();
+        spawn(&mut il, 1, true);
+        spawn(&mut il, 3, false);
+
+        let alarms = alarms_within(&mut il, 4);
+        assert!(alarms
+            .iter()
+            .any(|a| a.kind == AlarmKind::Fouling && a.trains == vec![1, 2] && a.node == 3));
+        assert!(il.fsm.emergency);
+    }
+
+    #[test]
+    fn test_auto_route_renewed_after_release() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(Command::CreateRoute {
+            start: PathBtn {
+                id: "X".to_string(),
+                kind: RawButtonKind::Pass,
+            },
+            end: train_btn("XF"),
+        })
+        .unwrap();
+        let first = il.fsm.route_by_start("X").unwrap().id;
+
+        il.handle(Command::SetAutoRoute {
+            start: train_btn("X"),
+            auto: true,
+        })
+        .unwrap();
+        assert!(il.fsm.route_by_start("X").unwrap().auto);
+
+        //列車通過後駛出站場，進路全部解鎖後自動重新建立
+        spawn(&mut il, 1, true);
+        let mut events = vec![];
+        for _ in 0..16000 {
+            events.extend(il.handle(Command::Tick).unwrap());
+        }
+
+        assert_eq!(il.fsm.trains[0].curr_node(), 6);
+        let route = il.fsm.route_by_start("X").unwrap();
+        assert_ne!(route.id, first);
+        assert!(route.auto);
+        assert!(il.fsm.sgn("X").state == SignalStatus::L);
+        //和手動建立一樣發出進路幀
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateRoute(r) => r.id == route.id && r.auto,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_auto_route_only_for_pass() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+
+        let result = il.handle(Command::SetAutoRoute {
+            start: train_btn("X"),
+            auto: true,
+        });
+        assert_eq!(
+            result.err(),
+            Some(InterlockingError::NotPassRoute("X".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_release_passed_section() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(Command::SpawnTrain {
+            at: 1,
+            dir: RawDirection::Right,
+            overrun: false,
+        })
+        .unwrap();
+        il.handle(receive_route()).unwrap();
+
+        //進入2後停留，再駛入3，停在進路終點
+        for _ in 0..4000 {
+            il.handle(Command::Tick).unwrap();
+        }
+
+        assert_eq!(il.fsm.trains[0].curr_node(), 3);
+        assert!(!il.fsm.node(2).is_lock);
+        assert!(il.fsm.node(3).is_lock);
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::PartiallyReleased);
+        assert_eq!(il.fsm.routes[0].released, vec![2]);
+    }
+
+    #[test]
+    fn test_blocked_elements_reject_route() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        let events = il
+            .handle(Command::BlockTrack {
+                track: "3G".to_string(),
+                blocked: true,
+            })
+            .unwrap();
+        assert!(il.fsm.node(3).is_blocked && il.fsm.node(4).is_blocked);
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateNode(n) => n.id == 4 && n.blocked,
+            _ => false,
+        }));
+        assert_eq!(
+            il.handle(receive_route()).err(),
+            Some(InterlockingError::NodeBlocked(3))
+        );
+
+        il.handle(Command::BlockTrack {
+            track: "3G".to_string(),
+            blocked: false,
+        })
+        .unwrap();
+        il.handle(Command::BlockSignal {
+            id: "S_L".to_string(),
+            blocked: true,
+        })
+        .unwrap();
+        assert_eq!(
+            il.handle(receive_route()).err(),
+            Some(InterlockingError::SignalBlocked("S_L".to_string()))
+        );
+
+        il.handle(Command::BlockSignal {
+            id: "S_L".to_string(),
+            blocked: false,
+        })
+        .unwrap();
+        assert!(il.handle(receive_route()).is_ok());
+    }
+
+    #[test]
+    fn test_manual_release_delayed() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        spawn(&mut il, 1, false);
+        il.handle(receive_route()).unwrap();
+        let start = || train_btn("X");
+
+        //接近區段有車，不能取消，只能總人解
+        assert_eq!(
+            il.handle(Command::CancelRoute { start: start() }).err(),
+            Some(InterlockingError::ApproachNotVacant(1))
+        );
+        il.handle(Command::ManualRelease { start: start() })
+            .unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::Releasing);
+        assert_eq!(
+            il.handle(Command::ManualRelease { start: start() }).err(),
+            Some(InterlockingError::RouteReleasing("X".to_string()))
+        );
+
+        for _ in 1..TRAIN_RELEASE_DELAY {
+            il.handle(Command::Tick).unwrap();
+        }
+        assert!(il.fsm.node(2).is_lock);
+        assert_eq!(il.fsm.trains[0].curr_node(), 1);
+
+        let events = il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.routes.is_empty());
+        assert!(!il.fsm.node(2).is_lock && !il.fsm.node(3).is_lock);
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateRoute(r) => r.state == RouteStatus::Released,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_manual_release_without_approach() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        il.handle(Command::ManualRelease {
+            start: train_btn("X"),
+        })
+        .unwrap();
+
+        assert!(il.fsm.routes.is_empty());
+        assert!(!il.fsm.node(2).is_lock);
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+    }
+
+    #[test]
+    fn test_fault_release_section() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        let release = |node| Command::FaultRelease { node: node };
+
+        assert_eq!(
+            il.handle(release(2)).err(),
+            Some(InterlockingError::SignalOpen("X".to_string()))
+        );
+        il.fsm.node_mut(2).state = NodeStatus::Unexpected;
+        il.handle(Command::Tick).unwrap();
+        assert_eq!(
+            il.handle(release(2)).err(),
+            Some(InterlockingError::NodeNotVacant(2))
+        );
+
+        il.fsm.node_mut(2).state = NodeStatus::Vacant;
+        il.handle(release(2)).unwrap();
+        assert!(!il.fsm.node(2).is_lock);
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::PartiallyReleased);
+        assert_eq!(
+            il.handle(release(2)).err(),
+            Some(InterlockingError::NodeNotLocked(2))
+        );
+        assert_eq!(
+            il.handle(release(4)).err(),
+            Some(InterlockingError::NodeNotLocked(4))
+        );
+
+        il.handle(release(3)).unwrap();
+        assert!(!il.fsm.node(3).is_lock);
+        assert!(il.fsm.routes.is_empty());
+    }
+
+    #[test]
+    fn test_guide_signal() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        let guide = |id: &str| Command::Guide {
+            start: id.to_string(),
+        };
+
+        assert_eq!(
+            il.handle(guide("X")).err(),
+            Some(InterlockingError::SignalOpen("X".to_string()))
+        );
+        //軌道電路故障，信號不能正常開放
+        il.fsm.node_mut(2).state = NodeStatus::Unexpected;
+        il.handle(Command::Tick).unwrap();
+        assert_eq!(
+            il.handle(guide("S_L")).err(),
+            Some(InterlockingError::NotHomeSignal("S_L".to_string()))
+        );
+
+        il.handle(guide("X")).unwrap();
+        il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::HB);
+        assert_eq!(il.fsm.routes[0].state, RouteStatus::Opened);
+
+        //列車進入後關閉
+        il.fsm.node_mut(2).state = NodeStatus::Occupied;
+        il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+    }
+
+    #[test]
+    fn test_force_turnout() {
+        let mut raw = station();
+        raw.nodes[4].turnout_id = vec![1];
+        let mut il = Interlocking::new(&raw).unwrap();
+        let force = |position| Command::ForceTurnout {
+            id: 1,
+            position: position,
+        };
+
+        let events = il.handle(force(TurnoutPosition::Reverse)).unwrap();
+        assert_eq!(il.fsm.turnout(1).position, TurnoutPosition::Reverse);
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateTurnout(t) => t.id == 1 && t.position == TurnoutPosition::Reverse,
+            _ => false,
+        }));
+
+        //進路鎖閉的道岔不能強扳
+        il.handle(opposite_receive_route()).unwrap();
+        assert_eq!(
+            il.handle(force(TurnoutPosition::Normal)).err(),
+            Some(InterlockingError::NodeLocked(5))
+        );
+        il.handle(Command::CancelRoute {
+            start: train_btn("XF"),
+        })
+        .unwrap();
+
+        //軌道電路故障時可以強扳，列車壓岔時不能
+        il.fsm.node_mut(5).state = NodeStatus::Unexpected;
+        il.handle(force(TurnoutPosition::Normal)).unwrap();
+        assert_eq!(il.fsm.turnout(1).position, TurnoutPosition::Normal);
+        il.fsm.node_mut(5).state = NodeStatus::Occupied;
+        assert_eq!(
+            il.handle(force(TurnoutPosition::Reverse)).err(),
+            Some(InterlockingError::NodeNotVacant(5))
+        );
+
+        il.fsm.node_mut(5).state = NodeStatus::Vacant;
+        il.fsm.turnouts.get_mut(&1).unwrap().is_blocked = true;
+        assert_eq!(
+            il.handle(force(TurnoutPosition::Reverse)).err(),
+            Some(InterlockingError::TurnoutBlocked(1))
+        );
+        assert_eq!(
+            il.handle(Command::ForceTurnout {
+                id: 9,
+                position: TurnoutPosition::Reverse,
+            })
+            .err(),
+            Some(InterlockingError::UnknownTurnout(9))
+        );
+    }
+
+    #[test]
+    fn test_inject_faults() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+
+        let events = il
+            .handle(Command::SetTrackFault {
+                node: 2,
+                fault: true,
+            })
+            .unwrap();
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateNode(n) => n.id == 2,
+            _ => false,
+        }));
+        il.handle(Command::Tick).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+        il.handle(Command::SetTrackFault {
+            node: 2,
+            fault: false,
+        })
+        .unwrap();
+        assert!(il.fsm.node(2).state == NodeStatus::Vacant);
+
+        //主燈絲斷絲後不能重複開放，排除後恢復
+        let fuse = |fused| Command::SetFilamentFault {
+            sgn: "X".to_string(),
+            fused: fused,
+        };
+        il.handle(fuse(true)).unwrap();
+        assert_eq!(
+            il.handle(receive_route()).err(),
+            Some(InterlockingError::FilamentFused("X".to_string()))
+        );
+        il.handle(fuse(false)).unwrap();
+        il.handle(receive_route()).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::U);
+        il.handle(fuse(true)).unwrap();
+        assert!(il.fsm.sgn("X").state == SignalStatus::H);
+
+        //有車的區段不是故障
+        spawn(&mut il, 1, false);
+        assert_eq!(
+            il.handle(Command::SetTrackFault {
+                node: 1,
+                fault: true,
+            })
+            .err(),
+            Some(InterlockingError::NodeNotVacant(1))
+        );
+        assert_eq!(
+            il.handle(Command::SetFilamentFault {
+                sgn: "Y".to_string(),
+                fused: true,
+            })
+            .err(),
+            Some(InterlockingError::UnknownSignal("Y".to_string()))
+        );
+    }
+}
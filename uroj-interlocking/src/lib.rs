@@ -0,0 +1,12 @@
+pub mod error;
+pub mod fsm;
+pub mod interlocking;
+pub mod raw_station;
+pub mod route;
+pub mod scenario;
+pub mod snapshot;
+pub mod storage;
+pub mod topo;
+
+pub use error::InterlockingError;
+pub use interlocking::{Command, Event, Interlocking, PathBtn};
@@ -0,0 +1,490 @@
+use std::collections::{HashSet, VecDeque};
+
+#[cfg(feature = "graphql")]
+use async_graphql::{Enum, SimpleObject};
+use serde::{Deserialize, Serialize};
+
+use crate::error::InterlockingError;
+use crate::fsm::*;
+use crate::interlocking::{Interlocking, PathBtn};
+use crate::raw_station::{RawButtonKind, RawDirection, RawSignalKind};
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+pub enum RouteKind {
+    Receive, //接車進路
+    Depart,  //發車進路
+    Pass,    //通過進路
+    Shunt,   //調車進路
+}
+
+//建立進路前逐項檢查的聯鎖條件
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+pub enum RouteCondition {
+    Filament,   //始端信號機主燈絲完好
+    Button,     //始終端按鈕未封鎖
+    SignalShut, //始端信號機尚未開放
+    Direction,  //路徑兩端的方向與按鈕一致
+    NoHostile,  //沒有已建立的敵對進路
+    Vacant,     //區段空閒
+    Unlocked,   //區段未被其他進路鎖閉
+    Unreserved, //區段未被牴觸進路征用
+    Turnout,    //道岔未單鎖
+    Unblocked,  //區段未封鎖
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct RouteCheck {
+    pub condition: RouteCondition,
+    pub passed: bool,
+    pub node: Option<NodeID>,
+    pub signal: Option<String>,
+    pub turnout: Option<TurnoutID>,
+    pub route: Option<RouteID>, //敵對進路
+}
+
+impl RouteCheck {
+    fn new(condition: RouteCondition, passed: bool) -> Self {
+        RouteCheck {
+            condition: condition,
+            passed: passed,
+            node: None,
+            signal: None,
+            turnout: None,
+            route: None,
+        }
+    }
+
+    fn at_node(condition: RouteCondition, passed: bool, node: NodeID) -> Self {
+        RouteCheck {
+            node: Some(node),
+            ..RouteCheck::new(condition, passed)
+        }
+    }
+
+    //未通過時對應的錯誤
+    pub fn error(&self) -> InterlockingError {
+        let signal = self.signal.clone().unwrap_or_default();
+        let node = self.node.unwrap_or_default();
+        match self.condition {
+            RouteCondition::Filament => InterlockingError::FilamentFused(signal),
+            RouteCondition::Button => InterlockingError::SignalBlocked(signal),
+            RouteCondition::SignalShut => InterlockingError::SignalOpen(signal),
+            RouteCondition::Direction => InterlockingError::NoAvailablePath(signal),
+            RouteCondition::NoHostile => InterlockingError::HostileRoute(signal),
+            RouteCondition::Vacant => InterlockingError::NodeNotVacant(node),
+            RouteCondition::Unlocked => InterlockingError::NodeLocked(node),
+            RouteCondition::Unreserved => InterlockingError::NodeInUse(node),
+            RouteCondition::Unblocked => InterlockingError::NodeBlocked(node),
+            RouteCondition::Turnout => {
+                InterlockingError::TurnoutBlocked(self.turnout.unwrap_or_default())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+pub enum RouteStatus {
+    Set,               //已鎖閉，信號未開放
+    Opened,            //信號已開放
+    Occupied,          //列車已進入
+    PartiallyReleased, //列車駛過的區段已逐段解鎖
+    Releasing,         //總人解後延時解鎖中
+    Released,          //全部解鎖，從登記中移除
+}
+
+pub type RouteID = usize;
+
+//已建立的進路
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Route {
+    pub id: RouteID,
+    pub kind: RouteKind,
+    pub start_sgn: String,
+    pub start_btn: RawButtonKind,
+    pub end_id: String, //信號機ID或者獨立按鈕ID
+    pub end_btn: RawButtonKind,
+    pub nodes: Vec<NodeID>,
+    pub reserved: Vec<NodeID>, //征用的牴觸區段，每個區段對應一次征用
+    pub signals: Vec<String>,  //隨進路開放的信號機
+    pub state: RouteStatus,
+    pub released: Vec<NodeID>, //已解鎖的區段
+    #[serde(default)]
+    pub auto: bool, //自動通過進路，解鎖後自動重新建立
+    #[serde(default)]
+    pub release_delay: u32, //延時解鎖剩餘的tick數
+}
+
+impl Route {
+    pub fn is_active(&self) -> bool {
+        self.state != RouteStatus::Released
+    }
+
+    //列車尚未進入，可以取消
+    pub fn can_cancel(&self) -> bool {
+        matches!(self.state, RouteStatus::Set | RouteStatus::Opened)
+    }
+
+    pub(crate) fn buttons(&self) -> (PathBtn, PathBtn) {
+        let start = PathBtn {
+            id: self.start_sgn.clone(),
+            kind: self.start_btn,
+        };
+        let end = PathBtn {
+            id: self.end_id.clone(),
+            kind: self.end_btn,
+        };
+        (start, end)
+    }
+}
+
+//可選的終端按鈕及其構成的進路
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Destination {
+    pub id: String, //信號機ID或者獨立按鈕ID
+    pub btn: RawButtonKind,
+    pub kind: RouteKind,
+    pub path: Vec<NodeID>,
+}
+
+//按鈕解析出的候選進路和各項條件的檢查結果
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct RoutePlan {
+    pub kind: RouteKind,
+    pub path: Vec<NodeID>,
+    pub dir: RawDirection, //路徑的行車方向
+    pub checks: Vec<RouteCheck>,
+}
+
+impl RoutePlan {
+    pub fn is_valid(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    //第一個未通過的條件
+    pub fn failure(&self) -> Option<InterlockingError> {
+        self.checks.iter().find(|c| !c.passed).map(|c| c.error())
+    }
+}
+
+impl Interlocking {
+    //與建立進路相同的檢查，不鎖閉任何區段。
+    //按鈕無法構成進路或站場中沒有路徑時返回錯誤，其餘條件記錄在檢查結果中
+    pub fn check_route(
+        &self,
+        start: &PathBtn,
+        end: &PathBtn,
+    ) -> Result<RoutePlan, InterlockingError> {
+        if start.id == end.id {
+            return Err(InterlockingError::SameStartEnd(start.id.clone()));
+        }
+        let topo = &self.topo;
+        let fsm = &self.fsm;
+        let known_sgn = |id: &str| {
+            fsm.sgns
+                .get(id)
+                .ok_or(InterlockingError::UnknownSignal(id.to_string()))
+        };
+        let ind_btn = |id: &str| {
+            topo.ind_btn
+                .get(id)
+                .cloned()
+                .ok_or(InterlockingError::UnknownButton(id.to_string()))
+        };
+        let no_route = || InterlockingError::NoRouteFound(start.id.clone());
+
+        let start_sgn = known_sgn(&start.id)?;
+        let (start_node, start_dir) = (start_sgn.protect_node_id, start_sgn.dir.reverse());
+
+        //這裏的方向是根據用戶輸入判斷的朝向，和最終尋到的路徑的前後朝向做判斷
+        //使用按鈕類型判斷進路類型
+        let (kind, goal_node, goal_dir) = match (start.kind, end.kind) {
+            //通過按鈕 -> 列車按鈕 = 通過進路
+            (RawButtonKind::Pass, RawButtonKind::Train) => {
+                let end_sgn = known_sgn(&end.id)?;
+                (RouteKind::Pass, end_sgn.protect_node_id, end_sgn.dir)
+            }
+            //通過按鈕 -> 列車終端按鈕 = 通過進路
+            (RawButtonKind::Pass, RawButtonKind::LZA) => {
+                (RouteKind::Pass, ind_btn(&end.id)?, start_dir)
+            }
+            //列車按鈕 -> 列車按鈕 = 接發車進路
+            (RawButtonKind::Train, RawButtonKind::Train) => {
+                let end_sgn = known_sgn(&end.id)?;
+                match (&start_sgn.kind, &end_sgn.kind) {
+                    //進站信號機 -> 出戰信號機 => 接車進路
+                    (RawSignalKind::HomeSignal, RawSignalKind::StartingSignal) => {
+                        (RouteKind::Receive, end_sgn.toward_node_id, end_sgn.dir)
+                    }
+                    //出站信號機 -> 進站信號機 => 發車進路
+                    (RawSignalKind::StartingSignal, RawSignalKind::HomeSignal) => {
+                        (RouteKind::Depart, end_sgn.protect_node_id, end_sgn.dir)
+                    }
+                    _ => return Err(no_route()),
+                }
+            }
+            //發車進路
+            (RawButtonKind::Train, RawButtonKind::LZA) => match start_sgn.kind {
+                RawSignalKind::StartingSignal => (RouteKind::Depart, ind_btn(&end.id)?, start_dir),
+                _ => return Err(no_route()),
+            },
+            //調車進路
+            (RawButtonKind::Shunt, RawButtonKind::Shunt) => {
+                //注意并置和差置
+                let end_id = topo
+                    .dif_relation
+                    .get(&end.id)
+                    .or(topo.jux_relation.get(&end.id))
+                    .unwrap_or(&end.id);
+
+                let end_sgn = known_sgn(end_id)?;
+                (
+                    RouteKind::Shunt,
+                    end_sgn.toward_node_id,
+                    end_sgn.dir.reverse(),
+                )
+            }
+            _ => return Err(no_route()),
+        };
+
+        //dir 是檢索到的可用方向
+        let (path, s_dir, g_dir) = topo
+            .available_path(start_node, goal_node)
+            .ok_or(InterlockingError::NoAvailablePath(start.id.clone()))?;
+
+        let with_signal = |check: RouteCheck| RouteCheck {
+            signal: Some(start.id.clone()),
+            ..check
+        };
+        let mut checks = vec![
+            with_signal(RouteCheck::new(
+                RouteCondition::Filament,
+                start_sgn.filament_status.0 != FilamentStatus::Fused,
+            )),
+            with_signal(RouteCheck::new(
+                RouteCondition::Button,
+                !start_sgn.is_blocked,
+            )),
+            with_signal(RouteCheck::new(
+                RouteCondition::SignalShut,
+                !start_sgn.is_allowed(),
+            )),
+            //進路方向 bound
+            with_signal(RouteCheck::new(
+                RouteCondition::Direction,
+                s_dir == start_dir && g_dir == goal_dir,
+            )),
+        ];
+
+        let hostile: Vec<RouteCheck> = fsm
+            .routes
+            .iter()
+            .filter(|r| r.is_active() && self.is_hostile(&start.id, &path, s_dir, r))
+            .map(|r| RouteCheck {
+                signal: Some(r.start_sgn.clone()),
+                route: Some(r.id),
+                ..RouteCheck::new(RouteCondition::NoHostile, false)
+            })
+            .collect();
+        //終端是信號機時其按鈕也不能封鎖
+        if let Some(end_sgn) = fsm.sgns.get(&end.id) {
+            checks.push(RouteCheck {
+                signal: Some(end.id.clone()),
+                ..RouteCheck::new(RouteCondition::Button, !end_sgn.is_blocked)
+            });
+        }
+
+        if hostile.is_empty() {
+            checks.push(RouteCheck::new(RouteCondition::NoHostile, true));
+        }
+        checks.extend(hostile);
+
+        //ensure that all nodes are not used or locked by another existing path
+        for id in &path {
+            let node = fsm.node(*id);
+            checks.push(RouteCheck::at_node(
+                RouteCondition::Vacant,
+                node.state == NodeStatus::Vacant,
+                *id,
+            ));
+            checks.push(RouteCheck::at_node(
+                RouteCondition::Unlocked,
+                !node.is_lock,
+                *id,
+            ));
+            checks.push(RouteCheck::at_node(
+                RouteCondition::Unreserved,
+                node.used_count == 0,
+                *id,
+            ));
+            checks.push(RouteCheck::at_node(
+                RouteCondition::Unblocked,
+                !node.is_blocked,
+                *id,
+            ));
+            for t in &node.turnouts {
+                checks.push(RouteCheck {
+                    turnout: Some(*t),
+                    ..RouteCheck::at_node(RouteCondition::Turnout, !fsm.turnout(*t).is_blocked, *id)
+                });
+            }
+        }
+
+        Ok(RoutePlan {
+            kind: kind,
+            path: path,
+            dir: s_dir,
+            checks: checks,
+        })
+    }
+
+    //已建立的進路是否與候選進路敵對：
+    //1. 兩進路的信號機在站場文件中聲明爲敵對信號（任一方聲明即可）
+    //2. 方向相反，且有區段屬於同一軌道電路（迎面接入同一股道）
+    //3. 方向相反，且兩進路的終端區段相鄰、相向（迎面衝突）
+    //同一區段由鎖閉檢查，牴觸區段由征用檢查，不在此列
+    fn is_hostile(
+        &self,
+        start_sgn: &str,
+        path: &[NodeID],
+        dir: RawDirection,
+        route: &Route,
+    ) -> bool {
+        let fsm = &self.fsm;
+        let topo = &self.topo;
+
+        let start_hostile = &fsm.sgn(start_sgn).hostile;
+        let declared = route.signals.iter().any(|s| {
+            start_hostile.contains(s) || fsm.sgn(s).hostile.iter().any(|h| h == start_sgn)
+        });
+        if declared {
+            return true;
+        }
+
+        //單區段進路推不出方向，只按聲明判斷
+        let route_dir = match route.nodes.get(0..2) {
+            Some(&[a, b]) => topo.direction(a, b),
+            _ => None,
+        };
+        let route_dir = match route_dir {
+            Some(d) => d,
+            None => return false,
+        };
+        if route_dir == dir {
+            return false;
+        }
+
+        let same_track = path
+            .iter()
+            .any(|a| route.nodes.iter().any(|b| topo.same_track(*a, *b)));
+        let head_on =
+            topo.direction(*path.last().unwrap(), *route.nodes.last().unwrap()) == Some(dir);
+        same_track || head_on
+    }
+
+    //按下始端按鈕後，當前能建立進路的終端按鈕
+    pub fn destinations(&self, start: &PathBtn) -> Result<Vec<Destination>, InterlockingError> {
+        let start_sgn = self
+            .fsm
+            .sgns
+            .get(&start.id)
+            .ok_or(InterlockingError::UnknownSignal(start.id.clone()))?;
+        let end_kinds: &[RawButtonKind] = match start.kind {
+            RawButtonKind::Pass | RawButtonKind::Train => {
+                &[RawButtonKind::Train, RawButtonKind::LZA]
+            }
+            RawButtonKind::Shunt => &[RawButtonKind::Shunt],
+            _ => &[],
+        };
+
+        //沿進路方向在r_graph上廣度優先，得到始端能到達的區段
+        let dir = start_sgn.dir.reverse();
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::new();
+        reached.insert(start_sgn.protect_node_id);
+        queue.push_back(start_sgn.protect_node_id);
+        while let Some(n) = queue.pop_front() {
+            for (_, t, d) in self.topo.r_graph.edges(n) {
+                if *d == dir && reached.insert(t) {
+                    queue.push_back(t);
+                }
+            }
+        }
+
+        //終端按鈕的候選：所在區段可達的信號機按鈕和獨立按鈕
+        let mut candidates = vec![];
+        for s in self.fsm.sgns.values() {
+            if s.id == start.id
+                || !(reached.contains(&s.protect_node_id) || reached.contains(&s.toward_node_id))
+            {
+                continue;
+            }
+            for kind in s.btns.iter().filter(|k| end_kinds.contains(k)) {
+                candidates.push(PathBtn {
+                    id: s.id.clone(),
+                    kind: *kind,
+                });
+            }
+        }
+        if end_kinds.contains(&RawButtonKind::LZA) {
+            for (id, n) in &self.topo.ind_btn {
+                if reached.contains(n) {
+                    candidates.push(PathBtn {
+                        id: id.clone(),
+                        kind: RawButtonKind::LZA,
+                    });
+                }
+            }
+        }
+
+        //按與建立進路相同的規則篩選，按鈕組合不成立的直接略過
+        Ok(candidates
+            .into_iter()
+            .filter_map(|end| {
+                let plan = self.check_route(start, &end).ok()?;
+                if !plan.is_valid() {
+                    return None;
+                }
+                Some(Destination {
+                    id: end.id,
+                    btn: end.kind,
+                    kind: plan.kind,
+                    path: plan.path,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interlocking::tests::{station, train_btn};
+
+    #[test]
+    fn test_check_route_does_not_lock() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.fsm.node_mut(3).state = NodeStatus::Occupied;
+
+        let plan = il.check_route(&train_btn("X"), &train_btn("S_L")).unwrap();
+        assert_eq!(plan.kind, RouteKind::Receive);
+        assert_eq!(plan.path, vec![2, 3]);
+        assert!(!plan.is_valid());
+        assert_eq!(plan.failure(), Some(InterlockingError::NodeNotVacant(3)));
+        assert!(!il.fsm.node(2).is_lock);
+    }
+
+    #[test]
+    fn test_destinations() {
+        let il = Interlocking::new(&station()).unwrap();
+
+        let found = il.destinations(&train_btn("X")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "S_L");
+        assert_eq!(found[0].kind, RouteKind::Receive);
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::raw_station::{RawButtonKind, RawDirection};
+
+use crate::error::InterlockingError;
+use crate::fsm::*;
+use crate::interlocking::{Command, Interlocking, PathBtn};
+
+//題目的初始局面，在玩家開始前佈置
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Scenario {
+    #[serde(default)]
+    pub occupied: Vec<NodeID>, //預先占用的區段
+    #[serde(default)]
+    pub trains: Vec<ScenarioTrain>, //預先放置的列車
+    #[serde(default)]
+    pub routes: Vec<ScenarioRoute>, //預先建立的進路
+    #[serde(default)]
+    pub fused_filaments: Vec<String>, //主燈絲斷絲的信號機
+    #[serde(default)]
+    pub blocked_turnouts: Vec<TurnoutID>, //封鎖的道岔
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ScenarioTrain {
+    pub at: NodeID,
+    pub dir: RawDirection,
+    #[serde(default)]
+    pub overrun: bool, //不理會信號，用於冒進和事故題目
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ScenarioRoute {
+    pub start_btn: RawButtonKind,
+    pub start_sgn: String,
+    pub end_btn: RawButtonKind,
+    pub end_id: String, //信號機ID或者獨立按鈕ID
+}
+
+impl Scenario {
+    pub fn merge(&mut self, other: Scenario) {
+        self.occupied.extend(other.occupied);
+        self.trains.extend(other.trains);
+        self.routes.extend(other.routes);
+        self.fused_filaments.extend(other.fused_filaments);
+        self.blocked_turnouts.extend(other.blocked_turnouts);
+    }
+}
+
+impl Interlocking {
+    //先建立進路，再佈置占用、故障和列車，否則進路會因區段占用而失敗
+    //佈置在玩家訂閲之前完成，產生的事件不必發出
+    pub fn apply_scenario(&mut self, scenario: &Scenario) -> Result<(), InterlockingError> {
+        for r in &scenario.routes {
+            let start = PathBtn {
+                id: r.start_sgn.clone(),
+                kind: r.start_btn,
+            };
+            let end = PathBtn {
+                id: r.end_id.clone(),
+                kind: r.end_btn,
+            };
+            self.handle(Command::CreateRoute {
+                start: start,
+                end: end,
+            })?;
+        }
+
+        for id in &scenario.occupied {
+            self.fsm
+                .nodes
+                .get_mut(id)
+                .ok_or(InterlockingError::UnknownNode(*id))?
+                .state = NodeStatus::Occupied;
+        }
+
+        for id in &scenario.fused_filaments {
+            self.fsm
+                .sgns
+                .get_mut(id)
+                .ok_or(InterlockingError::UnknownSignal(id.clone()))?
+                .filament_status
+                .0 = FilamentStatus::Fused;
+        }
+
+        for id in &scenario.blocked_turnouts {
+            self.fsm
+                .turnouts
+                .get_mut(id)
+                .ok_or(InterlockingError::UnknownTurnout(*id))?
+                .is_blocked = true;
+        }
+
+        for t in &scenario.trains {
+            self.handle(Command::SpawnTrain {
+                at: t.at,
+                dir: t.dir,
+                overrun: t.overrun,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interlocking::tests::{station, train_btn};
+
+    #[test]
+    fn test_apply_scenario() {
+        let mut raw = station();
+        raw.nodes[4].turnout_id = vec![1];
+        let mut il = Interlocking::new(&raw).unwrap();
+
+        let scenario: Scenario = serde_json::from_str(
+            r#"{"occupied":[6],
+            "trains":[{"at":1,"dir":"RIGHT"}],
+            "routes":[{"start_btn":"TRAIN","start_sgn":"X","end_btn":"TRAIN","end_id":"S_L"}],
+            "fused_filaments":["XF"],
+            "blocked_turnouts":[1]}"#,
+        )
+        .unwrap();
+        il.apply_scenario(&scenario).unwrap();
+
+        assert!(il.fsm.route_by_start("X").is_some());
+        assert!(il.fsm.node(6).state == NodeStatus::Occupied);
+        assert!(il.fsm.node(1).state == NodeStatus::Occupied);
+        assert_eq!(il.fsm.trains.len(), 1);
+        assert_eq!(il.fsm.trains[0].curr_node(), 1);
+        assert_eq!(il.fsm.trains[0].dir, RawDirection::Right);
+        assert!(il.fsm.sgn("XF").filament_status.0 == FilamentStatus::Fused);
+        assert!(il.fsm.turnout(1).is_blocked);
+
+        let result = il.handle(Command::CreateRoute {
+            start: train_btn("XF"),
+            end: train_btn("SF"),
+        });
+        assert_eq!(
+            result.err(),
+            Some(InterlockingError::FilamentFused("XF".to_string()))
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use serde::{Deserialize, Serialize};
+
+use crate::raw_station::RawDirection;
+
+use crate::error::InterlockingError;
+use crate::fsm::*;
+use crate::interlocking::Interlocking;
+use crate::route::{Route, RouteID};
+use crate::storage::StoredRoute;
+
+//聯鎖局面的快照，用於重啓或遷移後恢復
+//道岔的鎖閉隨所在區段，包含在區段快照中
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StateSnapshot {
+    pub nodes: Vec<NodeSnapshot>,
+    pub signals: Vec<SignalSnapshot>,
+    #[serde(default)]
+    pub turnouts: Vec<TurnoutSnapshot>,
+    #[serde(default)]
+    pub registry: Vec<Route>, //已建立的進路
+    #[serde(default)]
+    pub route_seq: RouteID,
+    #[serde(default)]
+    pub stored: Vec<StoredRoute>, //存儲的進路
+    #[serde(default)]
+    pub emergency: bool,
+    pub trains: Vec<TrainSnapshot>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct NodeSnapshot {
+    pub id: NodeID,
+    pub state: NodeStatus,
+    pub is_lock: bool,
+    pub once_occ: bool,
+    pub used_count: u32,
+    #[serde(default)]
+    pub is_blocked: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SignalSnapshot {
+    pub id: String,
+    pub state: SignalStatus,
+    pub filament_status: (FilamentStatus, FilamentStatus),
+    #[serde(default)]
+    pub is_blocked: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TurnoutSnapshot {
+    pub id: TurnoutID,
+    pub is_blocked: bool,
+    #[serde(default)]
+    pub position: TurnoutPosition,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TrainSnapshot {
+    pub id: TrainID,
+    pub process: f64,
+    pub dir: RawDirection,
+    pub past_node: Vec<NodeID>,
+    #[serde(default)]
+    pub overrun: bool,
+}
+
+impl Interlocking {
+    pub fn snapshot(&self) -> StateSnapshot {
+        let fsm = &self.fsm;
+
+        let nodes = fsm
+            .nodes
+            .values()
+            .map(|n| NodeSnapshot {
+                id: n.node_id,
+                state: n.state,
+                is_lock: n.is_lock,
+                once_occ: n.once_occ,
+                used_count: n.used_count,
+                is_blocked: n.is_blocked,
+            })
+            .collect();
+
+        let signals = fsm
+            .sgns
+            .values()
+            .map(|s| SignalSnapshot {
+                id: s.id.clone(),
+                state: s.state,
+                filament_status: s.filament_status,
+                is_blocked: s.is_blocked,
+            })
+            .collect();
+
+        let turnouts = fsm
+            .turnouts
+            .values()
+            .map(|t| TurnoutSnapshot {
+                id: t.id,
+                is_blocked: t.is_blocked,
+                position: t.position,
+            })
+            .collect();
+
+        let trains = fsm
+            .trains
+            .iter()
+            .map(|t| TrainSnapshot {
+                id: t.id,
+                process: t.process,
+                dir: t.dir,
+                past_node: t.past_node.clone(),
+                overrun: t.overrun,
+            })
+            .collect();
+
+        StateSnapshot {
+            nodes: nodes,
+            signals: signals,
+            turnouts: turnouts,
+            registry: fsm.routes.clone(),
+            route_seq: fsm.route_seq,
+            stored: fsm.stored.clone(),
+            emergency: fsm.emergency,
+            trains: trains,
+        }
+    }
+
+    //區段的鎖閉和征用已包含在區段狀態中，進路登記直接恢復
+    pub fn restore(&mut self, snapshot: &StateSnapshot) -> Result<(), InterlockingError> {
+        self.check_snapshot(snapshot)?;
+
+        let fsm = &mut self.fsm;
+        for n in &snapshot.nodes {
+            let node = fsm
+                .nodes
+                .get_mut(&n.id)
+                .ok_or(InterlockingError::UnknownNode(n.id))?;
+            node.state = n.state;
+            node.is_lock = n.is_lock;
+            node.once_occ = n.once_occ;
+            node.used_count = n.used_count;
+            node.is_blocked = n.is_blocked;
+        }
+
+        for s in &snapshot.signals {
+            let sgn = fsm
+                .sgns
+                .get_mut(&s.id)
+                .ok_or(InterlockingError::UnknownSignal(s.id.clone()))?;
+            sgn.state = s.state;
+            sgn.filament_status = s.filament_status;
+            sgn.is_blocked = s.is_blocked;
+        }
+
+        for t in &snapshot.turnouts {
+            let turnout = fsm
+                .turnouts
+                .get_mut(&t.id)
+                .ok_or(InterlockingError::UnknownTurnout(t.id))?;
+            turnout.is_blocked = t.is_blocked;
+            turnout.position = t.position;
+        }
+
+        for t in &snapshot.trains {
+            fsm.trains.push(Train {
+                id: t.id,
+                process: t.process,
+                dir: t.dir,
+                past_node: t.past_node.clone(),
+                dwell: 0,
+                overrun: t.overrun,
+            });
+        }
+
+        fsm.routes = snapshot.registry.clone();
+        fsm.route_seq = snapshot.route_seq;
+        fsm.stored = snapshot.stored.clone();
+        fsm.emergency = snapshot.emergency;
+
+        Ok(())
+    }
+
+    //列車和進路引用的ID先逐一核對，不讓錯誤的快照到運行時才panic
+    fn check_snapshot(&self, snapshot: &StateSnapshot) -> Result<(), InterlockingError> {
+        let fsm = &self.fsm;
+        let node = |id: &NodeID| {
+            fsm.nodes
+                .get(id)
+                .map(|_| ())
+                .ok_or(InterlockingError::UnknownNode(*id))
+        };
+        let sgn = |id: &String| {
+            fsm.sgns
+                .get(id)
+                .map(|_| ())
+                .ok_or(InterlockingError::UnknownSignal(id.clone()))
+        };
+        //終端可以是信號機或獨立按鈕
+        let end = |id: &String| {
+            if fsm.sgns.contains_key(id) || self.topo.ind_btn.contains_key(id) {
+                Ok(())
+            } else {
+                Err(InterlockingError::UnknownButton(id.clone()))
+            }
+        };
+
+        for t in &snapshot.trains {
+            if t.past_node.is_empty() {
+                return Err(InterlockingError::InvalidTrain(t.id));
+            }
+            t.past_node.iter().try_for_each(node)?;
+        }
+
+        for r in &snapshot.registry {
+            sgn(&r.start_sgn)?;
+            end(&r.end_id)?;
+            r.signals.iter().try_for_each(sgn)?;
+            r.nodes
+                .iter()
+                .chain(&r.reserved)
+                .chain(&r.released)
+                .try_for_each(node)?;
+        }
+
+        for r in &snapshot.stored {
+            sgn(&r.start_sgn)?;
+            end(&r.end_id)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interlocking::tests::{receive_route, station, train_btn};
+    use crate::interlocking::Command;
+
+    //已建立進路、存儲的敵對進路和一列接近的列車
+    fn playing() -> Interlocking {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(Command::SpawnTrain {
+            at: 1,
+            dir: RawDirection::Right,
+            overrun: false,
+        })
+        .unwrap();
+        il.handle(receive_route()).unwrap();
+        il.handle(Command::StoreRoute {
+            start: train_btn("XF"),
+            end: train_btn("SF"),
+        })
+        .unwrap();
+        il
+    }
+
+    fn round_trip(il: &Interlocking) -> Interlocking {
+        let json = serde_json::to_string(&il.snapshot()).unwrap();
+        let mut restored = Interlocking::new(&station()).unwrap();
+        restored
+            .restore(&serde_json::from_str(&json).unwrap())
+            .unwrap();
+        restored
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut il = playing();
+        let mut restored = round_trip(&il);
+        assert_eq!(restored.fsm.routes.len(), 1);
+        assert_eq!(restored.fsm.stored.len(), 1);
+        assert_eq!(restored.fsm.trains.len(), 1);
+        assert!(restored.fsm.node(2).is_lock);
+        assert!(restored.fsm.sgn("X").state == SignalStatus::U);
+
+        //恢復後對同樣的操作給出同樣的結果
+        let cancel = || Command::CancelRoute {
+            start: train_btn("XF"),
+        };
+        assert_eq!(il.handle(cancel()).err(), restored.handle(cancel()).err());
+        for _ in 0..4000 {
+            il.handle(Command::Tick).unwrap();
+            restored.handle(Command::Tick).unwrap();
+        }
+        assert_eq!(il.fsm.trains[0].past_node, restored.fsm.trains[0].past_node);
+        assert_eq!(il.fsm.routes[0].state, restored.fsm.routes[0].state);
+        assert_eq!(il.fsm.routes[0].released, restored.fsm.routes[0].released);
+        let cancel = || Command::CancelRoute {
+            start: train_btn("X"),
+        };
+        assert_eq!(
+            il.handle(cancel()).err(),
+            Some(InterlockingError::RouteOccupied("X".to_string()))
+        );
+        assert_eq!(
+            restored.handle(cancel()).err(),
+            Some(InterlockingError::RouteOccupied("X".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_restore_rejects_unknown_ids() {
+        let mut snapshot = playing().snapshot();
+        snapshot.trains[0].past_node.clear();
+        let mut il = Interlocking::new(&station()).unwrap();
+        assert_eq!(
+            il.restore(&snapshot).err(),
+            Some(InterlockingError::InvalidTrain(snapshot.trains[0].id))
+        );
+
+        let mut snapshot = playing().snapshot();
+        snapshot.registry[0].nodes.push(99);
+        assert_eq!(
+            il.restore(&snapshot).err(),
+            Some(InterlockingError::UnknownNode(99))
+        );
+
+        let mut snapshot = playing().snapshot();
+        snapshot.stored[0].start_sgn = "Y".to_string();
+        assert_eq!(
+            il.restore(&snapshot).err(),
+            Some(InterlockingError::UnknownSignal("Y".to_string()))
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::InterlockingError;
+use crate::interlocking::{Event, Interlocking, PathBtn};
+use crate::raw_station::RawButtonKind;
+use crate::route::RouteID;
+
+//存儲的進路（進路預排）：因占用、鎖閉或敵對暫時不能建立，條件滿足後按存儲順序自動建立
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredRoute {
+    pub id: RouteID,
+    pub start_sgn: String,
+    pub start_btn: RawButtonKind,
+    pub end_id: String, //信號機ID或者獨立按鈕ID
+    pub end_btn: RawButtonKind,
+    pub blocked_by: InterlockingError, //最近一次嘗試時的阻礙
+    #[serde(default)]
+    pub auto: bool, //等待重新建立的自動通過進路
+}
+
+impl StoredRoute {
+    fn buttons(&self) -> (PathBtn, PathBtn) {
+        let start = PathBtn {
+            id: self.start_sgn.clone(),
+            kind: self.start_btn,
+        };
+        let end = PathBtn {
+            id: self.end_id.clone(),
+            kind: self.end_btn,
+        };
+        (start, end)
+    }
+}
+
+impl Interlocking {
+    //能建立則立即建立，暫時不能建立則存儲，按鈕無效等其他錯誤直接返回
+    pub(crate) fn store_path(
+        &mut self,
+        start: PathBtn,
+        end: PathBtn,
+    ) -> Result<(), InterlockingError> {
+        let stored = self
+            .fsm
+            .stored
+            .iter()
+            .any(|r| r.start_sgn == start.id && r.end_id == end.id);
+        if stored {
+            return Ok(());
+        }
+
+        let plan = self.check_route(&start, &end)?;
+        let blocked_by = match plan.failure() {
+            None => return self.create_path(start, end, false).map(|_| ()),
+            Some(e) if e.is_transient() => e,
+            Some(e) => return Err(e),
+        };
+
+        self.store_blocked(start, end, blocked_by, false);
+        Ok(())
+    }
+
+    pub(crate) fn store_blocked(
+        &mut self,
+        start: PathBtn,
+        end: PathBtn,
+        blocked_by: InterlockingError,
+        auto: bool,
+    ) {
+        debug!("route from {} stored: {}", start.id, blocked_by);
+        self.fsm.route_seq += 1;
+        let route = StoredRoute {
+            id: self.fsm.route_seq,
+            start_sgn: start.id,
+            start_btn: start.kind,
+            end_id: end.id,
+            end_btn: end.kind,
+            blocked_by: blocked_by,
+            auto: auto,
+        };
+        self.fsm.stored.push(route);
+        self.send_stored();
+    }
+
+    pub(crate) fn cancel_stored(&mut self, id: RouteID) -> Result<(), InterlockingError> {
+        let idx = self
+            .fsm
+            .stored
+            .iter()
+            .position(|r| r.id == id)
+            .ok_or(InterlockingError::UnknownStoredRoute(id))?;
+        self.fsm.stored.remove(idx);
+        self.send_stored();
+        Ok(())
+    }
+
+    //依次嘗試建立存儲的進路，先存儲的優先；仍不能建立的記下新的阻礙
+    pub(crate) fn process_stored(&mut self) {
+        if self.fsm.stored.is_empty() {
+            return;
+        }
+
+        let stored = std::mem::take(&mut self.fsm.stored);
+        let mut remain = Vec::new();
+        let mut changed = false;
+        for mut route in stored {
+            let (start, end) = route.buttons();
+            match self.create_path(start, end, route.auto) {
+                Ok(_) => {
+                    debug!("stored route {} is set", route.id);
+                    changed = true;
+                }
+                Err(e) => {
+                    if e != route.blocked_by {
+                        route.blocked_by = e;
+                        changed = true;
+                    }
+                    remain.push(route);
+                }
+            }
+        }
+        self.fsm.stored = remain;
+
+        if changed {
+            self.send_stored();
+        }
+    }
+
+    fn send_stored(&mut self) {
+        self.events
+            .push(Event::UpdateStoredRoutes(self.fsm.stored.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interlocking::tests::{receive_route, station, train_btn};
+    use crate::interlocking::Command;
+
+    fn store_opposite() -> Command {
+        Command::StoreRoute {
+            start: train_btn("XF"),
+            end: train_btn("SF"),
+        }
+    }
+
+    #[test]
+    fn test_stored_route_set_after_release() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+
+        il.handle(store_opposite()).unwrap();
+        assert_eq!(il.fsm.stored.len(), 1);
+        assert_eq!(
+            il.fsm.stored[0].blocked_by,
+            InterlockingError::HostileRoute("X".to_string())
+        );
+
+        //敵對進路取消後自動建立
+        let events = il
+            .handle(Command::CancelRoute {
+                start: train_btn("X"),
+            })
+            .unwrap();
+        assert!(il.fsm.stored.is_empty());
+        assert!(il.fsm.route_by_start("XF").is_some());
+        assert!(events.iter().any(|e| match e {
+            Event::UpdateStoredRoutes(r) => r.is_empty(),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_cancel_stored_route() {
+        let mut il = Interlocking::new(&station()).unwrap();
+        il.handle(receive_route()).unwrap();
+        il.handle(store_opposite()).unwrap();
+
+        let id = il.fsm.stored[0].id;
+        il.handle(Command::CancelStoredRoute { id: id }).unwrap();
+        assert!(il.fsm.stored.is_empty());
+
+        let result = il.handle(Command::CancelStoredRoute { id: id });
+        assert_eq!(
+            result.err(),
+            Some(InterlockingError::UnknownStoredRoute(id))
+        );
+    }
+}
@@ -5,32 +5,31 @@
 
 use std::collections::HashMap;
 
-use crate::raw_station::{IndButton, RawDirection, RawNode, RawSignal};
+use crate::raw_station::{IndButton, RawDirection, RawJointKind, RawNode, RawSignal};
 use petgraph::{
     algo,
     graphmap::{DiGraphMap, UnGraphMap},
 };
 
-use super::fsm::NodeID;
+use crate::fsm::NodeID;
 
 //站場圖
-pub(crate) struct Topo {
-    pub(crate) r_graph: DiGraphMap<NodeID, RawDirection>,
-    pub(crate) s_graph: UnGraphMap<NodeID, ()>,
-    pub(crate) dif_relation: HashMap<String, String>,
-    pub(crate) jux_relation: HashMap<String, String>,
-    pub(crate) ind_btn: HashMap<String, NodeID>,
+pub struct Topo {
+    pub r_graph: DiGraphMap<NodeID, RawDirection>,
+    pub s_graph: UnGraphMap<NodeID, ()>,
+    pub c_graph: UnGraphMap<NodeID, ()>, //隔侵限絕緣相鄰的區段
+    pub track: HashMap<NodeID, String>,  //區段所屬的軌道電路
+    pub dif_relation: HashMap<String, String>,
+    pub jux_relation: HashMap<String, String>,
+    pub ind_btn: HashMap<String, NodeID>,
     // b_graph: UnGraphMap<NodeID, ()>,
 }
 
 impl Topo {
-    pub(crate) fn new(
-        nodes: &Vec<RawNode>,
-        sgns: &Vec<RawSignal>,
-        ind_btns: &Vec<IndButton>,
-    ) -> Self {
+    pub fn new(nodes: &Vec<RawNode>, sgns: &Vec<RawSignal>, ind_btns: &Vec<IndButton>) -> Self {
         let mut r_graph = DiGraphMap::new();
         let mut s_graph = UnGraphMap::new();
+        let mut c_graph = UnGraphMap::new();
         // let b_graph= UnGraphMap::new();
 
         nodes.iter().for_each(|n| {
@@ -43,6 +42,16 @@ impl Topo {
             for i in &n.conflicted_nodes {
                 s_graph.add_edge(n.id, *i, ());
             }
+            if n.joint.0 == RawJointKind::Clearance {
+                for i in &n.left_adj {
+                    c_graph.add_edge(n.id, *i, ());
+                }
+            }
+            if n.joint.1 == RawJointKind::Clearance {
+                for i in &n.right_adj {
+                    c_graph.add_edge(n.id, *i, ());
+                }
+            }
         });
 
         let jux_relation = sgns
@@ -55,6 +64,8 @@ impl Topo {
             .filter_map(|s| s.dif_sgn.as_ref().map(|k| (s.id.clone(), k.clone())))
             .collect();
 
+        let track = nodes.iter().map(|n| (n.id, n.track_id.clone())).collect();
+
         let ind_btn = ind_btns
             .iter()
             .map(|b| (b.id.clone(), b.protect_node_id))
@@ -63,14 +74,16 @@ impl Topo {
         Topo {
             r_graph: r_graph,
             s_graph: s_graph,
+            c_graph: c_graph,
             jux_relation: jux_relation,
             dif_relation: dif_relation,
             ind_btn: ind_btn,
+            track: track,
         }
     }
 
     //可能な進路を探す
-    pub(crate) fn available_path(
+    pub fn available_path(
         &self,
         start: NodeID,
         goal: NodeID,
@@ -95,13 +108,23 @@ impl Topo {
 
         let end_dir = self
             .r_graph
-            .edge_weight(maybe_path[maybe_path.len() - 2], maybe_path[maybe_path.len() - 1])?
+            .edge_weight(
+                maybe_path[maybe_path.len() - 2],
+                maybe_path[maybe_path.len() - 1],
+            )?
             .clone();
 
         Some((maybe_path, entry_dir, end_dir))
     }
 
-    pub(crate) fn direction(&self, from: NodeID, to: NodeID) -> Option<RawDirection> {
+    pub fn same_track(&self, a: NodeID, b: NodeID) -> bool {
+        match (self.track.get(&a), self.track.get(&b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    pub fn direction(&self, from: NodeID, to: NodeID) -> Option<RawDirection> {
         self.r_graph.edge_weight(from, to).map(|d| d.clone())
     }
 }
@@ -0,0 +1,149 @@
+use async_graphql::Context;
+
+use uroj_common::utils::Role as AuthRole;
+
+use crate::{get_id_from_ctx, get_role_from_ctx, instance::Instance};
+
+//實例操作權限
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Permission {
+    View,     //只讀查詢：玩家、出題人、管理員以及持有實例token者
+    Operate,  //操作命令：僅玩家
+    Instruct, //教師命令（故障注入、放車、結束等）：出題人或管理員
+}
+
+//由請求頭或websocket初始化參數帶入的實例token
+pub(crate) struct InstanceToken(pub(crate) String);
+
+pub(crate) fn authorize(
+    ctx: &Context<'_>,
+    perm: Permission,
+    player: &str,
+    creator: Option<&str>,
+    token: &str,
+) -> Result<(), String> {
+    let caller = Caller {
+        uid: get_id_from_ctx(ctx).ok(),
+        role: get_role_from_ctx(ctx),
+        token: ctx.data_opt::<InstanceToken>().map(|t| t.0.clone()),
+    };
+    caller.check(perm, player, creator, token)
+}
+
+//請求者的身份，取自令牌和實例token
+struct Caller {
+    uid: Option<String>,
+    role: Option<AuthRole>,
+    token: Option<String>,
+}
+
+impl Caller {
+    fn check(
+        &self,
+        perm: Permission,
+        player: &str,
+        creator: Option<&str>,
+        token: &str,
+    ) -> Result<(), String> {
+        let uid = self.uid.as_deref();
+        let is_player = uid == Some(player);
+        let is_instructor = self.role == Some(AuthRole::Admin) || (uid.is_some() && uid == creator);
+        let has_token = self.token.as_deref() == Some(token);
+
+        let allowed = match perm {
+            Permission::View => is_player || is_instructor || has_token,
+            Permission::Operate => is_player,
+            Permission::Instruct => is_instructor,
+        };
+
+        if allowed {
+            Ok(())
+        } else if uid.is_none() {
+            Err("Not Login".to_string())
+        } else {
+            Err("Forbiden".to_string())
+        }
+    }
+}
+
+impl Instance {
+    pub(crate) fn authorize(&self, ctx: &Context<'_>, perm: Permission) -> Result<(), String> {
+        authorize(
+            ctx,
+            perm,
+            &self.player,
+            self.creator.as_deref(),
+            &self.token,
+        )
+    }
+
+    //只憑實例token查看的用户，計入旁觀者列表
+    pub(crate) fn is_spectator(&self, ctx: &Context<'_>) -> bool {
+        self.authorize(ctx, Permission::Operate).is_err()
+            && self.authorize(ctx, Permission::Instruct).is_err()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(uid: Option<&str>, role: AuthRole, token: Option<&str>) -> Caller {
+        Caller {
+            uid: uid.map(|u| u.to_string()),
+            role: Some(role),
+            token: token.map(|t| t.to_string()),
+        }
+    }
+
+    fn check(caller: &Caller, perm: Permission) -> Result<(), String> {
+        caller.check(perm, "player", Some("teacher"), "ABCDEF")
+    }
+
+    #[test]
+    fn test_player() {
+        let player = caller(Some("player"), AuthRole::User, None);
+        assert!(check(&player, Permission::View).is_ok());
+        assert!(check(&player, Permission::Operate).is_ok());
+        assert_eq!(
+            check(&player, Permission::Instruct),
+            Err("Forbiden".to_string())
+        );
+    }
+
+    #[test]
+    fn test_instructor() {
+        for instructor in &[
+            caller(Some("teacher"), AuthRole::User, None),
+            caller(Some("admin"), AuthRole::Admin, None),
+        ] {
+            assert!(check(instructor, Permission::View).is_ok());
+            assert!(check(instructor, Permission::Instruct).is_ok());
+            //教師不能代替玩家操作
+            assert_eq!(
+                check(instructor, Permission::Operate),
+                Err("Forbiden".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_spectator() {
+        let spectator = caller(None, AuthRole::User, Some("ABCDEF"));
+        assert!(check(&spectator, Permission::View).is_ok());
+        assert_eq!(
+            check(&spectator, Permission::Operate),
+            Err("Not Login".to_string())
+        );
+        assert_eq!(
+            check(&spectator, Permission::Instruct),
+            Err("Not Login".to_string())
+        );
+
+        let stranger = caller(Some("other"), AuthRole::User, Some("WRONG"));
+        assert_eq!(
+            check(&stranger, Permission::View),
+            Err("Forbiden".to_string())
+        );
+    }
+}
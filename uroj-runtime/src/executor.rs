@@ -0,0 +1,133 @@
+use std::{env, fs, time::Duration};
+
+use actix_web::{client::Client, rt::time::interval};
+use log::{info, warn};
+use serde_json::{json, Value};
+use uroj_common::utils::{create_token, Role as AuthRole};
+
+use crate::SharedInstancePool;
+
+//心跳間隔，需小於uroj-api判定失效的時長
+const HEARTBEAT_INTERVAL_SECS: u64 = 10;
+
+//本執行器對外的地址，也是在uroj-api中的標識
+pub(crate) fn executor_addr() -> String {
+    env::var("EXECUTOR_ADDR").unwrap_or("http://localhost:8003/".to_string())
+}
+
+//執行器啓動後向uroj-api註冊，之後定時上報負載
+pub fn spawn_heartbeat(ins_pool: SharedInstancePool) {
+    let api_url = env::var("API_URL").unwrap_or("http://localhost:8001/".to_string());
+    let addr = executor_addr();
+
+    actix_web::rt::spawn(async move {
+        let mut executor_id = None;
+        let mut interval = interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let result = match executor_id {
+                None => register(&api_url, &addr).await.map(|id| {
+                    info!("registered as executor {}", id);
+                    id
+                }),
+                Some(id) => {
+                    let running = ins_pool.lock().await.len() as i32;
+                    heartbeat(&api_url, &addr, id, running).await.map(|_| id)
+                }
+            };
+            //失敗後重新註冊，uroj-api 重啓或將本執行器判爲失效時也能恢復
+            executor_id = match result {
+                Ok(id) => Some(id),
+                Err(e) => {
+                    warn!("heartbeat to {} failed: {}", api_url, e);
+                    None
+                }
+            };
+        }
+    });
+}
+
+async fn register(api_url: &str, addr: &str) -> Result<i32, String> {
+    let data = call_api(
+        api_url,
+        addr,
+        "mutation ($url: String!) { registerExecutor(url: $url) { id } }",
+        json!({ "url": addr }),
+    )
+    .await?;
+
+    data["registerExecutor"]["id"]
+        .as_i64()
+        .map(|id| id as i32)
+        .ok_or("invalid response".to_string())
+}
+
+async fn heartbeat(api_url: &str, addr: &str, id: i32, running: i32) -> Result<(), String> {
+    call_api(
+        api_url,
+        addr,
+        "mutation ($id: Int!, $input: HeartbeatInput!) { heartbeat(id: $id, input: $input) { id } }",
+        json!({
+            "id": id,
+            "input": {
+                "runningInstances": running,
+                "cpuUsage": cpu_usage(),
+                "memUsage": mem_usage(),
+            }
+        }),
+    )
+    .await
+    .map(|_| ())
+}
+
+async fn call_api(
+    api_url: &str,
+    addr: &str,
+    query: &str,
+    variables: Value,
+) -> Result<Value, String> {
+    let token = create_token(format!("executor@{}", addr), AuthRole::Executor);
+    let mut resp = Client::default()
+        .post(api_url)
+        .header("Authorization", format!("Bearer {}", token))
+        .send_json(&json!({ "query": query, "variables": variables }))
+        .await
+        .map_err(|e| e.to_string())?;
+    let body: Value = resp.json().await.map_err(|e| e.to_string())?;
+
+    if let Some(errors) = body.get("errors") {
+        return Err(errors.to_string());
+    }
+    Ok(body["data"].clone())
+}
+
+//一分鐘平均負載除以核心數
+fn cpu_usage() -> f32 {
+    let load = fs::read_to_string("/proc/loadavg")
+        .ok()
+        .and_then(|s| s.split_whitespace().next().and_then(|l| l.parse::<f32>().ok()))
+        .unwrap_or(0.);
+    let cores = fs::read_to_string("/proc/cpuinfo")
+        .map(|s| s.lines().filter(|l| l.starts_with("processor")).count())
+        .unwrap_or(1)
+        .max(1);
+
+    load / cores as f32
+}
+
+//已用內存比例
+fn mem_usage() -> f32 {
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find(|l| l.starts_with(name))
+            .and_then(|l| l.split_whitespace().nth(1))
+            .and_then(|v| v.parse::<f32>().ok())
+    };
+
+    match (field("MemTotal:"), field("MemAvailable:")) {
+        (Some(total), Some(available)) if total > 0. => 1. - available / total,
+        _ => 0.,
+    }
+}
@@ -1,16 +1,30 @@
 use actix_web::{HttpRequest, HttpResponse, Result, get, post, web};
-use async_graphql::{Schema, http::{playground_source, GraphQLPlaygroundConfig}};
+use async_graphql::{Data, Schema, http::{playground_source, GraphQLPlaygroundConfig}};
 use async_graphql_actix_web::{Request, Response, WSSubscription};
-use uroj_common::utils::get_claims;
+use uroj_common::utils::{get_claims, get_claims_from_header};
 
-use crate::models::AppSchema;
+use crate::{auth::InstanceToken, models::AppSchema};
 
+//websocket 的登錄信息和實例token放在connection_init的payload中
 pub(crate) async fn index_ws(
     schema: web::Data<AppSchema>,
     req: HttpRequest,
     payload: web::Payload,
 ) -> Result<HttpResponse> {
-    WSSubscription::start(Schema::clone(&schema), &req, payload)
+    WSSubscription::start_with_initializer(Schema::clone(&schema), &req, payload, |value| async move {
+        let mut data = Data::default();
+        if let Some(claims) = value
+            .get("Authorization")
+            .and_then(|v| v.as_str())
+            .and_then(get_claims_from_header)
+        {
+            data.insert(claims);
+        }
+        if let Some(token) = value.get("token").and_then(|v| v.as_str()) {
+            data.insert(InstanceToken(token.to_string()));
+        }
+        Ok(data)
+    })
 }
 
 pub(crate) async fn index_playground() -> HttpResponse {
@@ -24,6 +38,14 @@ pub(crate) async fn index_playground() -> HttpResponse {
 pub(crate) async fn index(schema: web::Data<AppSchema>, http_req: HttpRequest, req: Request) -> Response {
     let mut query = req.into_inner();
 
+    if let Some(token) = http_req
+        .headers()
+        .get("Instance-Token")
+        .and_then(|v| v.to_str().ok())
+    {
+        query = query.data(InstanceToken(token.to_string()));
+    }
+
     let maybe_claims = get_claims(http_req);
     if let Some(claims) = maybe_claims {
         query = query.data(claims);
@@ -0,0 +1,42 @@
+use async_graphql::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub(crate) enum AuditKind {
+    Spad,              //冒進信號
+    Collision,         //列車衝突
+    Fouling,           //侵限
+    EmergencyRelease,  //解除緊急停車
+    AutoRoute,         //自動通過進路的開關
+    CriticalOperation, //計數的關鍵操作
+    Fault,             //教師注入或排除故障
+}
+
+//一條審計記錄，時間取實例時鐘
+#[derive(SimpleObject, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    pub(crate) timestamp: i64,
+    pub(crate) kind: AuditKind,
+    pub(crate) detail: String,
+}
+
+//實例内的險情和關鍵操作，隨快照保存，供教師覆盤
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub(crate) fn record(&mut self, timestamp: i64, kind: AuditKind, detail: String) {
+        self.entries.push(AuditEntry {
+            timestamp: timestamp,
+            kind: kind,
+            detail: detail,
+        });
+    }
+
+    pub(crate) fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.clone()
+    }
+}
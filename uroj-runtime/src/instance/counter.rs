@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use async_graphql::*;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+//帶鉛封的關鍵操作
+#[derive(Enum, EnumIter, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Debug)]
+pub(crate) enum CounterKind {
+    ManualRelease, //總人解
+    FaultRelease,  //區故解
+    Guide,         //引導
+    ForceTurnout,  //道岔強扳
+}
+
+#[derive(SimpleObject, Clone, Serialize, Deserialize)]
+pub(crate) struct CounterData {
+    pub(crate) kind: CounterKind,
+    pub(crate) count: u32,
+}
+
+//計數器，每次使用關鍵操作加一，交接班時核對
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Counters {
+    counts: HashMap<CounterKind, u32>,
+}
+
+impl Counters {
+    pub(crate) fn count(&mut self, kind: CounterKind) -> CounterData {
+        let count = self.counts.entry(kind).or_default();
+        *count += 1;
+        CounterData {
+            kind: kind,
+            count: *count,
+        }
+    }
+
+    //未使用過的也列出，值爲0
+    pub(crate) fn values(&self) -> Vec<CounterData> {
+        CounterKind::iter()
+            .map(|kind| CounterData {
+                kind: kind,
+                count: self.counts.get(&kind).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
@@ -1,16 +1,21 @@
 use async_graphql::*;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 use std::{collections::HashMap, str::FromStr};
+use uuid::Uuid;
 
+use uroj_db::connection::Conn;
+use uroj_db::models::instance::Instance as InstanceModel;
 use uroj_db::models::instance_question::InstanceQuestion;
 use uroj_db::models::question::Question as QuestionModel;
-
-use crate::{get_conn_from_ctx, instance::fsm::NodeID};
+use uroj_interlocking::fsm::NodeID;
 
 use super::{FrameSender, GameFrame};
 
 type QID = i32;
+
+//冒進信號扣的分數
+pub(crate) const SPAD_PENALTY: i32 = 10;
+
 #[derive(Deserialize, Serialize, Debug, Clone, SimpleObject)]
 pub(crate) struct Question {
     id: QID,
@@ -58,10 +63,12 @@ pub(crate) enum QuestionStatus {
     Skip,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub(crate) struct ExamManager {
     pub(crate) question: Vec<Question>,
     pub(crate) score: HashMap<QID, i32>,
+    #[serde(default)]
+    pub(crate) penalty: i32, //險情累計扣分，沒有完成的題目也照扣
 }
 
 impl ExamManager {
@@ -72,6 +79,7 @@ impl ExamManager {
                 .map(|(id, q)| Question::new(*id, q))
                 .collect(),
             score: HashMap::new(),
+            penalty: 0,
         }
     }
 
@@ -81,27 +89,28 @@ impl ExamManager {
         }
     }
 
-    pub(crate) fn sync_score_to_db(&self, iid: String, ctx: &Context<'_>) -> Result<(), String> {
-        let conn = get_conn_from_ctx(ctx);
-        let uuid = Uuid::from_str(&iid).map_err(|_|"myname")?;
+    pub(crate) fn sync_score_to_db(&self, iid: &str, conn: &Conn) -> Result<(), String> {
+        let uuid = Uuid::from_str(iid).map_err(|_| "invalid instance id")?;
 
         for (iqid, score) in &self.score {
-            let iq = InstanceQuestion::find_one(uuid, *iqid, &conn)
+            let iq = InstanceQuestion::find_one(uuid, *iqid, conn)
                 .map_err(|_| "cannot find a question")?;
 
-            iq.update_score(*score, &conn)
+            iq.update_score(*score, conn)
                 .map_err::<String, _>(|_| "cannot update score".into())?;
         }
 
-        Ok(())
+        //扣分單獨寫回實例，不依賴已完成的題目
+        InstanceModel::find_one(uuid, conn)
+            .and_then(|i| i.update_penalty(self.penalty, conn))
+            .map_err(|_| "cannot update penalty".into())
+    }
+
+    pub(crate) fn deduct(&mut self, points: i32) {
+        self.penalty += points;
     }
 
-    pub(crate) async fn update_state(
-        &mut self,
-        iqid: QID,
-        state: QuestionStatus,
-        sender: &FrameSender,
-    ) {
+    pub(crate) fn update_state(&mut self, iqid: QID, state: QuestionStatus, sender: &FrameSender) {
         let mut score = 0;
 
         if state == QuestionStatus::Completed {
@@ -118,7 +127,6 @@ impl ExamManager {
             id: iqid,
             state: state,
         })
-        .send_via(sender)
-        .await;
+        .send_via(sender);
     }
 }
@@ -0,0 +1,171 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use async_graphql::*;
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use super::GameFrame;
+
+//斷線重連時從歷史幀中補發，按實例時鐘和幀數同時限制；
+//列車每毫秒都有幀，超出幀數後缺口過大的訂閲者改收全局狀態
+const HISTORY_MILLIS: i64 = 30_000;
+const HISTORY_SIZE: usize = 1024;
+const CHANNEL_SIZE: usize = 32;
+
+type Seq = u64;
+
+//帶序號和實例時鐘的幀
+#[derive(SimpleObject, Clone)]
+pub(crate) struct Frame {
+    pub(crate) seq: Seq,       //實例内遞增，從1開始
+    pub(crate) timestamp: i64, //實例時鐘，毫秒
+    pub(crate) data: GameFrame,
+}
+
+struct FrameLog {
+    started_at: DateTime<Utc>, //實例時鐘的起點
+    next_seq: Seq,
+    history: VecDeque<Frame>,
+}
+
+//實例的幀發送端，負責編號、記錄歷史並廣播
+#[derive(Clone)]
+pub(crate) struct FrameSender {
+    log: Arc<Mutex<FrameLog>>,
+    tx: Sender<Frame>,
+}
+
+impl FrameSender {
+    pub(crate) fn new() -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_SIZE);
+        FrameSender {
+            log: Arc::new(Mutex::new(FrameLog {
+                started_at: Utc::now(),
+                next_seq: 1,
+                history: VecDeque::with_capacity(HISTORY_SIZE),
+            })),
+            tx: tx,
+        }
+    }
+
+    pub(crate) fn send(&self, data: GameFrame) {
+        let mut log = self.log.lock().unwrap();
+        let frame = Frame {
+            seq: log.next_seq,
+            timestamp: (Utc::now() - log.started_at).num_milliseconds(),
+            data: data,
+        };
+        log.next_seq += 1;
+        let expired = frame.timestamp - HISTORY_MILLIS;
+        while log.history.len() >= HISTORY_SIZE
+            || log.history.front().map_or(false, |f| f.timestamp < expired)
+        {
+            log.history.pop_front();
+        }
+        log.history.push_back(frame.clone());
+        //持鎖廣播，保證訂閲者收到的順序和序號一致；無訂閲者時忽略
+        let _ = self.tx.send(frame);
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<Frame> {
+        self.tx.subscribe()
+    }
+
+    pub(crate) fn last_seq(&self) -> Seq {
+        self.log.lock().unwrap().next_seq - 1
+    }
+
+    //序號在seq之後的全部幀，已不在歷史中則返回None
+    pub(crate) fn since(&self, seq: Seq) -> Option<Vec<Frame>> {
+        let log = self.log.lock().unwrap();
+        let first = log.next_seq - log.history.len() as Seq;
+        if seq >= log.next_seq || seq + 1 < first {
+            return None;
+        }
+        Some(
+            log.history
+                .iter()
+                .skip((seq + 1 - first) as usize)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    pub(crate) fn elapsed(&self) -> i64 {
+        (Utc::now() - self.log.lock().unwrap().started_at).num_milliseconds()
+    }
+
+    //從快照恢復時回撥實例時鐘
+    pub(crate) fn set_elapsed(&self, elapsed: i64) {
+        self.log.lock().unwrap().started_at = Utc::now() - Duration::milliseconds(elapsed);
+    }
+
+    //不經廣播、不佔序號的幀，用於給單個訂閲者補發全局狀態
+    pub(crate) fn frame_at(&self, seq: Seq, data: GameFrame) -> Frame {
+        Frame {
+            seq: seq,
+            timestamp: self.elapsed(),
+            data: data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast::TryRecvError;
+    use uroj_interlocking::fsm::EmergencyStop;
+
+    fn send(tx: &FrameSender, n: usize) {
+        for _ in 0..n {
+            tx.send(GameFrame::EmergencyStop(EmergencyStop { active: true }));
+        }
+    }
+
+    fn seqs(frames: Vec<Frame>) -> Vec<Seq> {
+        frames.into_iter().map(|f| f.seq).collect()
+    }
+
+    #[test]
+    fn test_since_within_history() {
+        let tx = FrameSender::new();
+        send(&tx, 3);
+
+        assert_eq!(tx.last_seq(), 3);
+        assert_eq!(tx.since(0).map(seqs), Some(vec![1, 2, 3]));
+        assert_eq!(tx.since(1).map(seqs), Some(vec![2, 3]));
+        assert_eq!(tx.since(3).map(seqs), Some(vec![]));
+        //客户端的序號不可能超前
+        assert!(tx.since(4).is_none());
+    }
+
+    #[test]
+    fn test_since_gap_too_large() {
+        let tx = FrameSender::new();
+        send(&tx, HISTORY_SIZE + 5);
+
+        assert_eq!(tx.log.lock().unwrap().history.len(), HISTORY_SIZE);
+        //最早的幀已被丟棄，只能重發全局狀態
+        assert!(tx.since(1).is_none());
+        assert!(tx.since(4).is_none());
+        let last = tx.last_seq();
+        assert_eq!(tx.since(5).map(|f| f.len()), Some(HISTORY_SIZE));
+        assert_eq!(tx.since(last - 1).map(seqs), Some(vec![last]));
+    }
+
+    #[test]
+    fn test_lagged_receiver() {
+        let tx = FrameSender::new();
+        let mut rx = tx.subscribe();
+        send(&tx, CHANNEL_SIZE + 2);
+
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Lagged(_))));
+        let next = rx.try_recv().unwrap();
+        assert_eq!(next.seq, 3);
+        //丟失的幀仍在歷史中，可以按序號補發
+        assert_eq!(tx.since(0).map(|f| f.len()), Some(CHANNEL_SIZE + 2));
+    }
+}
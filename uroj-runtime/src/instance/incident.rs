@@ -0,0 +1,44 @@
+use async_graphql::*;
+use serde::{Deserialize, Serialize};
+use uroj_interlocking::{
+    fsm::{Alarm, UpdateSignal},
+    Interlocking,
+};
+
+use super::station::RouteData;
+
+//事故報告，記下險情發生時涉及的進路和全部信號機的顯示，供事故分析
+#[derive(SimpleObject, Clone, Serialize, Deserialize)]
+pub(crate) struct IncidentReport {
+    pub(crate) timestamp: i64, //實例時鐘
+    pub(crate) alarm: Alarm,
+    pub(crate) routes: Vec<RouteData>, //涉事列車所在區段上的進路
+    pub(crate) signals: Vec<UpdateSignal>,
+}
+
+impl IncidentReport {
+    pub(crate) fn new(timestamp: i64, alarm: &Alarm, il: &Interlocking) -> Self {
+        let mut nodes = vec![alarm.node];
+        nodes.extend(
+            il.fsm
+                .trains
+                .iter()
+                .filter(|t| alarm.trains.contains(&t.id))
+                .map(|t| t.curr_node()),
+        );
+
+        IncidentReport {
+            timestamp: timestamp,
+            alarm: alarm.clone(),
+            routes: il
+                .fsm
+                .routes
+                .iter()
+                .filter(|r| r.nodes.iter().any(|n| nodes.contains(n)))
+                .cloned()
+                .map(|r| r.into())
+                .collect(),
+            signals: il.fsm.sgns.values().map(|s| s.to_update_signal()).collect(),
+        }
+    }
+}
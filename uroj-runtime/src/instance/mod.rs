@@ -1,417 +1,595 @@
+pub(crate) mod audit;
+pub(crate) mod counter;
 pub(crate) mod exam;
-pub(crate) mod fsm;
+pub(crate) mod frame;
+pub(crate) mod incident;
+pub(crate) mod snapshot;
+pub(crate) mod spectator;
 pub(crate) mod station;
-pub(crate) mod topo;
 
 use async_graphql::*;
+use async_stream::stream;
+use chrono::{DateTime, Utc};
+use futures::Stream;
 use log::debug;
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
 use strum_macros::*;
 use tokio::{
-    sync::{
-        broadcast::{self, Receiver, Sender},
-        Mutex,
-    },
+    sync::{broadcast::RecvError, mpsc, oneshot},
+    time::interval,
 };
 
 use serde::{Deserialize, Serialize};
 use uroj_db::models::question::Question as QuestionModel;
+use uroj_interlocking::{
+    fsm::*,
+    raw_station::{RawDirection, RawStation},
+    route::{RouteID, RoutePlan},
+    scenario::Scenario,
+    Command as IlCommand, Event, Interlocking, InterlockingError, PathBtn,
+};
 
-use crate::raw_station::{RawDirection, RawSignalKind, RawStation};
-
-use self::topo::Topo;
-use self::{exam::ExamManager, fsm::*};
+use self::frame::Frame;
+pub(crate) use self::frame::FrameSender;
 use self::{
-    exam::UpdateQuestion,
-    station::{ButtonKind, LayoutData, NodeData, SignalData},
+    audit::{AuditEntry, AuditKind, AuditLog},
+    counter::{CounterData, CounterKind, Counters},
+    exam::{UpdateQuestion, SPAD_PENALTY},
+    incident::IncidentReport,
+    station::{
+        DestinationData, GlobalStatusData, LayoutData, NodeData, RouteData, SignalData,
+        StoredRoutesData,
+    },
 };
+use self::{
+    exam::{ExamManager, QuestionsData},
+    spectator::SpectatorList,
+};
+
+//列車步進的間隔
+const TICK_INTERVAL: Duration = Duration::from_millis(1);
+const MAILBOX_SIZE: usize = 64;
 
 #[derive(Union, Clone, Serialize, Deserialize)]
 pub(crate) enum GameFrame {
     UpdateSignal(UpdateSignal),
     UpdateNode(UpdateNode),
-    UpdateGlobalStatus(GlobalStatus),
+    UpdateGlobalStatus(GlobalStatusData),
     MoveTrain(MoveTrain),
     UpdateQuestion(UpdateQuestion),
+    UpdateRoute(RouteData),
+    Alarm(Alarm),
+    EmergencyStop(EmergencyStop),
+    UpdateStoredRoutes(StoredRoutesData),
+    UpdateCounter(CounterData),
+    UpdateTurnout(UpdateTurnout),
 }
 
 impl GameFrame {
-    pub(crate) async fn send_via(&self, sender: &Sender<GameFrame>) {
+    pub(crate) fn send_via(&self, sender: &FrameSender) {
         sender.send(self.clone());
     }
 }
 
-pub(crate) type FrameSender = Sender<GameFrame>;
+impl From<Event> for GameFrame {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::UpdateSignal(s) => GameFrame::UpdateSignal(s),
+            Event::UpdateNode(n) => GameFrame::UpdateNode(n),
+            Event::MoveTrain(t) => GameFrame::MoveTrain(t),
+            Event::UpdateRoute(r) => GameFrame::UpdateRoute(r.into()),
+            Event::Alarm(a) => GameFrame::Alarm(a),
+            Event::EmergencyStop(s) => GameFrame::EmergencyStop(s),
+            Event::UpdateStoredRoutes(r) => GameFrame::UpdateStoredRoutes(r.into()),
+            Event::UpdateTurnout(t) => GameFrame::UpdateTurnout(t),
+        }
+    }
+}
+
+//交給actor執行的命令
+type Command = Box<dyn FnOnce(&mut InstanceState) + Send>;
 
+//實例的句柄，放在實例池中；局面由actor獨佔，只能通過命令訪問
 pub struct Instance {
     pub(crate) layout: LayoutData,
-    pub(crate) fsm: Arc<Mutex<InstanceFSM>>,
-    pub(crate) topo: Arc<Topo>,
-    pub(crate) exam: Option<ExamManager>,
+    pub(crate) questions: Option<QuestionsData>, //考試實例的題目
     pub(crate) tx: FrameSender,
-    pub(crate) _rx: Receiver<GameFrame>,
+    pub(crate) player: String,
+    pub(crate) creator: Option<String>,
+    pub(crate) token: String,
+    pub(crate) spectators: SpectatorList,
+    pub(crate) last_active: Mutex<DateTime<Utc>>, //玩家最後一次操作的時間
+    commands: mpsc::Sender<Command>,
 }
 
-pub(crate) struct PathBtn {
-    pub(crate) id: String, //signal or button id
-    pub(crate) kind: ButtonKind,
+//實例的局面，只在actor中訪問
+pub(crate) struct InstanceState {
+    pub(crate) il: Interlocking,
+    pub(crate) exam: Option<ExamManager>,
+    pub(crate) audit: AuditLog,
+    pub(crate) incidents: Vec<IncidentReport>,
+    pub(crate) counters: Counters,
+    pub(crate) tx: FrameSender,
 }
 
 impl Instance {
+    //創建實例並佈置題目的初始局面
     pub(crate) fn new(cfg: &InstanceConfig) -> Result<Self, String> {
-        let signals = &cfg.station.signals;
-        let nodes = &cfg.station.nodes;
-        let ind_btns = &cfg.station.independent_btns;
-
-        let topo = Topo::new(nodes, signals, ind_btns);
-
-        //創建fsm
-        let mut fsm_sgns: HashMap<String, Signal> =
-            signals.iter().map(|s| (s.id.clone(), s.into())).collect();
-        let mut fsm_nodes: HashMap<NodeID, Node> = nodes.iter().map(|n| (n.id, n.into())).collect();
-        let mut stn_sgns: HashMap<_, SignalData> =
-            signals.iter().map(|s| (s.id.clone(), s.into())).collect();
-        let stn_nodes: HashMap<_, NodeData> = nodes.iter().map(|n| (n.id, n.into())).collect();
-
-        //需要配置stn_sgn 的 dir 和 pos 缺省
-        //需要配置fsm_node的left和right，
-        //fsm_sgn的dir缺省
-        for s in signals {
-            let pid = s.protect_node_id;
-            let tid = s.toward_node_id;
-
-            let dir = topo
-                .direction(pid, tid)
-                .or(s.dir.into())
-                .ok_or(format!("invalid signal {}", s.id))?;
-
-            let stn_sgn = stn_sgns.get_mut(&s.id).unwrap();
-            let fsm_sgn = fsm_sgns.get_mut(&s.id).unwrap();
-
-            let p_node = fsm_nodes
-                .get_mut(&pid)
-                .ok_or(format!("unknown node id: {}", pid))?;
-            let p_node_stn = stn_nodes.get(&pid).ok_or("fuck you bro")?;
-
-            //對信號機進行所屬
-            fsm_sgn.dir = dir;
-            stn_sgn.dir = dir;
-            match dir {
-                RawDirection::Left => {
-                    stn_sgn.pos = p_node_stn.left_p.clone();
-                    p_node.left_sgn_id = Some(s.id.clone());
-                }
-                RawDirection::Right => {
-                    stn_sgn.pos = p_node_stn.right_p.clone();
-                    p_node.right_sgn_id = Some(s.id.clone());
-                }
+        let mut scenario = Scenario::default();
+        for q in cfg.questions.values() {
+            if let Some(s) = &q.initial_state {
+                scenario.merge(serde_json::from_str(s).map_err(|e| e.to_string())?);
             }
         }
 
-        let layout = LayoutData {
-            title: cfg.station.title.clone(),
-            nodes: stn_nodes.values().cloned().collect(),
-            signals: stn_sgns.values().cloned().collect(),
-        };
+        let (mut state, layout) = InstanceState::build(cfg)?;
+        state
+            .il
+            .apply_scenario(&scenario)
+            .map_err(|e| e.to_string())?;
+        Ok(Self::spawn(cfg, state, layout))
+    }
 
-        let fsm = InstanceFSM {
-            sgns: fsm_sgns
-                .drain()
-                .map(|(id, sgn)| (id.clone(), Mutex::new(sgn)))
-                .collect(),
-            nodes: fsm_nodes
-                .drain()
-                .map(|(id, node)| (id, Mutex::new(node)))
-                .collect(),
-
-            trains: Arc::new(Mutex::new(Vec::new())),
+    //啓動actor，所有句柄被丟棄後actor退出，訂閲隨之結束
+    pub(crate) fn spawn(cfg: &InstanceConfig, state: InstanceState, layout: LayoutData) -> Self {
+        let (commands, rx) = mpsc::channel(MAILBOX_SIZE);
+        let instance = Instance {
+            layout: layout,
+            questions: state.exam.as_ref().map(|e| e.get_questions()),
+            tx: state.tx.clone(),
+            player: cfg.player.clone(),
+            creator: cfg.creator.clone(),
+            token: cfg.token.clone(),
+            spectators: SpectatorList::default(),
+            last_active: Mutex::new(Utc::now()),
+            commands: commands,
         };
 
-        let exam = if (&cfg.questions).is_empty() {
-            None
-        } else {
-            Some(ExamManager::new(&cfg.questions))
-        };
+        tokio::spawn(run(state, rx));
+        instance
+    }
 
-        let (tx, rx) = broadcast::channel(32);
-        Ok(Instance {
-            fsm: Arc::new(Mutex::new(fsm)),
-            topo: Arc::new(topo),
-            layout: layout,
-            exam: exam,
-            tx: tx,
-            _rx: rx,
+    //在actor中執行f並等待結果
+    pub(crate) async fn call<F, R>(&self, f: F) -> Result<R, String>
+    where
+        F: FnOnce(&mut InstanceState) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let command: Command = Box::new(move |state| {
+            let _ = reply.send(f(state));
+        });
+        self.commands
+            .clone()
+            .send(command)
+            .await
+            .map_err(|_| "instance is stopped".to_string())?;
+        result.await.map_err(|_| "instance is stopped".to_string())
+    }
+
+    //聯鎖拒絕時錯誤碼和出錯的ID放在extensions中
+    pub(crate) async fn create_path(&self, start: PathBtn, end: PathBtn) -> Result<()> {
+        self.call(move |s| {
+            s.execute(IlCommand::CreateRoute {
+                start: start,
+                end: end,
+            })
         })
+        .await?
+        .map_err(|e| e.extend())
     }
 
-    //パースを作成する
-    pub(crate) async fn create_path(
-        &self,
-        start: PathBtn,
-        end: PathBtn,
-    ) -> Result<Vec<NodeID>, String> {
-        debug!("flag0");
-        if start.id == end.id {
-            return Err("start and end cannot be same".to_string());
-        }
-        let topo = &self.topo;
-        let fsm = &self.fsm.lock().await;
-
-        let mut start_sgn = fsm.sgn(&start.id).await;
-
-        let (start_node, start_dir) = (start_sgn.protect_node_id, start_sgn.dir.reverse());
-        //這裏的方向是根據用戶輸入判斷的朝向，和最終尋到的路徑的前後朝向做判斷
-        //使用按鈕類型判斷進路類型
-        let (mut is_pass, mut is_send, mut is_recv, mut is_shnt) = (false, false, false, false);
-
-        let (goal_node, goal_dir) = match (start.kind, end.kind) {
-            //通過按鈕 -> 列車按鈕 = 通過進路
-            (ButtonKind::Pass, ButtonKind::Train) => {
-                is_pass = true;
-                let end_sgn = fsm.sgn(&end.id).await;
-                (end_sgn.protect_node_id, end_sgn.dir.clone())
-            }
-            //通過按鈕 -> 列車終端按鈕 = 通過進路
-            (ButtonKind::Pass, ButtonKind::LZA) => {
-                is_pass = true;
-                let node_id = self
-                    .topo
-                    .ind_btn
-                    .get(&end.id)
-                    .ok_or(format!("unknown button id: {}", &end.id))?
-                    .clone();
-
-                (node_id, start_dir.clone())
-            }
-            //列車按鈕 -> 列車按鈕 = 接發車進路
-            (ButtonKind::Train, ButtonKind::Train) => {
-                let end_sgn = fsm.sgn(&end.id).await;
-                match (&start_sgn.kind, &end_sgn.kind) {
-                    //進站信號機 -> 出戰信號機 => 接車進路
-                    (RawSignalKind::HomeSignal, RawSignalKind::StartingSignal) => {
-                        is_recv = true;
-                        (end_sgn.toward_node_id, end_sgn.dir.clone())
-                    }
-                    //出站信號機 -> 進站信號機 => 發車進路
-                    (RawSignalKind::StartingSignal, RawSignalKind::HomeSignal) => {
-                        is_send = true;
-                        (end_sgn.protect_node_id, end_sgn.dir.clone())
-                    }
-                    _ => return Err("no route found".to_string()),
-                }
-            }
-            //發車進路
-            (ButtonKind::Train, ButtonKind::LZA) => {
-                is_send = true;
-                match start_sgn.kind {
-                    RawSignalKind::StartingSignal => {
-                        let node_id = self
-                            .topo
-                            .ind_btn
-                            .get(&end.id)
-                            .ok_or(format!("unknown button id: {}", &end.id))?
-                            .clone();
-
-                        (node_id, start_dir.clone())
-                    }
+    //暫時不能建立的進路存儲起來，條件滿足後自動建立
+    pub(crate) async fn store_path(&self, start: PathBtn, end: PathBtn) -> Result<()> {
+        self.call(move |s| {
+            s.execute(IlCommand::StoreRoute {
+                start: start,
+                end: end,
+            })
+        })
+        .await?
+        .map_err(|e| e.extend())
+    }
 
-                    _ => return Err("no route found".to_string()),
-                }
-            }
-            //調車進路
-            (ButtonKind::Shunt, ButtonKind::Shunt) => {
-                is_shnt = true;
-                //注意并置和差置
-                let end_id = self
-                    .topo
-                    .dif_relation
-                    .get(&end.id)
-                    .or(topo.jux_relation.get(&end.id))
-                    .unwrap_or(&end.id);
-
-                let end_sgn = fsm.sgn(end_id).await;
-
-                (end_sgn.toward_node_id, end_sgn.dir.reverse())
-            }
-            _ => return Err("no route found".to_string()),
-        };
+    pub(crate) async fn cancel_stored(&self, route: RouteID) -> Result<()> {
+        self.call(move |s| s.execute(IlCommand::CancelStoredRoute { id: route }))
+            .await?
+            .map_err(|e| e.extend())
+    }
 
-        //dir 是檢索到的可用方向
-        let (maybe_path, s_dir, g_dir) = self
-            .topo
-            .available_path(start_node, goal_node)
-            .ok_or("no available path exists")?;
+    pub(crate) async fn stored_routes(&self) -> Result<StoredRoutesData, String> {
+        self.call(|s| s.il.fsm.stored.clone().into()).await
+    }
 
-        //進路方向 bound
-        if s_dir != start_dir || g_dir != goal_dir {
-            return Err("no available route exists".to_string());
-        }
+    pub(crate) async fn check_route(&self, start: PathBtn, end: PathBtn) -> Result<RoutePlan> {
+        self.call(move |s| s.il.check_route(&start, &end))
+            .await?
+            .map_err(|e| e.extend())
+    }
 
-        //ensure that all nodes are not used or locked by another existing path
-        let mut sgn_id = Vec::new();
-        debug!("flag1");
-        for id in &maybe_path {
-            debug!("flag1-{}", id);
+    pub(crate) async fn destinations(&self, start: PathBtn) -> Result<Vec<DestinationData>> {
+        self.call(move |s| s.il.destinations(&start))
+            .await?
+            .map(|found| found.into_iter().map(|d| d.into()).collect())
+            .map_err(|e| e.extend())
+    }
 
-            let node = fsm.node(*id).await;
-            if node.state != NodeStatus::Vacant {
-                debug!("{} is not vacant", id);
-                return Err("target path is not vacant".into());
-            }
-            if node.is_lock {
-                debug!("{} is not locked", id);
-                return Err("target path is conflicting".into());
-            }
-            if node.used_count > 0 {
-                return Err("target path is mutex".into());
-            }
+    //設置或取消自動通過進路，記入審計
+    pub(crate) async fn set_auto_route(&self, start: PathBtn, auto: bool) -> Result<()> {
+        self.call(move |s| {
+            let sgn = start.id.clone();
+            s.execute(IlCommand::SetAutoRoute {
+                start: start,
+                auto: auto,
+            })?;
+            let timestamp = s.tx.elapsed();
+            let detail = format!(
+                "auto route from {} turned {}",
+                sgn,
+                if auto { "on" } else { "off" }
+            );
+            s.audit.record(timestamp, AuditKind::AutoRoute, detail);
+            Ok(())
+        })
+        .await?
+        .map_err(|e: InterlockingError| e.extend())
+    }
 
-            let sgn = match s_dir {
-                RawDirection::Left => node.right_sgn_id.clone(),
-                RawDirection::Right => node.left_sgn_id.clone(),
-            };
-            if let Some(sgn) = sgn {
-                sgn_id.push(sgn);
-            }
-        }
+    pub(crate) async fn cancel_path(&self, start: PathBtn) -> Result<()> {
+        self.call(move |s| s.execute(IlCommand::CancelRoute { start: start }))
+            .await?
+            .map_err(|e| e.extend())
+    }
 
-        //锁闭区段
-        debug!("flag2");
-        for id in &maybe_path {
-            debug!("flag2-{}", id);
-
-            let mut node = fsm.node(*id).await;
-            debug!("trying to lock: {}", id);
-            node.lock(&self.tx).await;
-            node.once_occ = false; //重置曾占用flag
-            for id in topo.s_graph.neighbors(*id) {
-                let mut node = fsm.node(id).await;
-                node.used_count += 1;
-                debug!("{}.used_count= {}", id, node.used_count);
-            }
-        }
+    //區段、按鈕和股道封鎖
+    pub(crate) async fn block_node(&self, node: NodeID, blocked: bool) -> Result<()> {
+        self.call(move |s| {
+            s.execute(IlCommand::BlockNode {
+                id: node,
+                blocked: blocked,
+            })
+        })
+        .await?
+        .map_err(|e| e.extend())
+    }
 
-        if is_recv {
-            let kind = fsm.node(goal_node).await.kind.clone();
-            start_sgn.open_recv(kind, &self.tx).await;
-        }
+    pub(crate) async fn block_signal(&self, sgn: String, blocked: bool) -> Result<()> {
+        self.call(move |s| {
+            s.execute(IlCommand::BlockSignal {
+                id: sgn,
+                blocked: blocked,
+            })
+        })
+        .await?
+        .map_err(|e| e.extend())
+    }
 
-        if is_pass || is_send {
-            start_sgn.open(&self.tx).await;
-        }
+    pub(crate) async fn block_track(&self, track: String, blocked: bool) -> Result<()> {
+        self.call(move |s| {
+            s.execute(IlCommand::BlockTrack {
+                track: track,
+                blocked: blocked,
+            })
+        })
+        .await?
+        .map_err(|e| e.extend())
+    }
 
-        if is_shnt {
-            start_sgn.open(&self.tx).await;
+    //鉛封按鈕，聯鎖執行成功後才計數
+    pub(crate) async fn manually_release(&self, start: PathBtn) -> Result<()> {
+        self.call(move |s| {
+            let detail = format!("manual release of route from {}", start.id);
+            s.execute(IlCommand::ManualRelease { start: start })?;
+            s.count(CounterKind::ManualRelease, detail);
+            Ok(())
+        })
+        .await?
+        .map_err(|e: InterlockingError| e.extend())
+    }
 
-            for id in sgn_id {
-                if id == start_sgn.id {
-                    continue;
-                }
-                let mut sgn = fsm.sgn(&id).await;
-                sgn.open(&self.tx).await;
-            }
-        }
+    pub(crate) async fn fault_release(&self, node: NodeID) -> Result<()> {
+        self.call(move |s| {
+            s.execute(IlCommand::FaultRelease { node: node })?;
+            s.count(
+                CounterKind::FaultRelease,
+                format!("fault release of node {}", node),
+            );
+            Ok(())
+        })
+        .await?
+        .map_err(|e: InterlockingError| e.extend())
+    }
 
-        Ok(maybe_path)
+    pub(crate) async fn open_guide(&self, sgn: String) -> Result<()> {
+        self.call(move |s| {
+            let detail = format!("guide signal {} opened", sgn);
+            s.execute(IlCommand::Guide { start: sgn })?;
+            s.count(CounterKind::Guide, detail);
+            Ok(())
+        })
+        .await?
+        .map_err(|e: InterlockingError| e.extend())
     }
 
-    //進路を消す
-    pub(crate) async fn cancel_path(&self, start: PathBtn) -> Result<(), String> {
-        let fsm = &self.fsm.lock().await;
-        let topo = &self.topo;
+    pub(crate) async fn force_turnout(
+        &self,
+        turnout: TurnoutID,
+        position: TurnoutPosition,
+    ) -> Result<()> {
+        self.call(move |s| {
+            let detail = format!("turnout {} forced to {:?}", turnout, position);
+            s.execute(IlCommand::ForceTurnout {
+                id: turnout,
+                position: position,
+            })?;
+            s.count(CounterKind::ForceTurnout, detail);
+            Ok(())
+        })
+        .await?
+        .map_err(|e: InterlockingError| e.extend())
+    }
 
-        let mut start_sgn = fsm.sgn(&start.id).await;
-        if !start_sgn.is_allowed() {
-            return Err("not find a existed route".into());
-        }
+    pub(crate) async fn counters(&self) -> Result<Vec<CounterData>, String> {
+        self.call(|s| s.counters.values()).await
+    }
 
-        let (start_node, start_dir) = (start_sgn.protect_node_id, start_sgn.dir.reverse());
+    pub(crate) async fn spawn_train(
+        &self,
+        at: NodeID,
+        dir: RawDirection,
+        overrun: bool,
+    ) -> Result<()> {
+        self.call(move |s| {
+            s.execute(IlCommand::SpawnTrain {
+                at: at,
+                dir: dir,
+                overrun: overrun,
+            })
+        })
+        .await?
+        .map_err(|e| e.extend())
+    }
 
-        debug!("开始寻径");
-        let maybe_route = Self::find_a_route(fsm, topo, start_node, &start_dir)
+    pub(crate) async fn routes(&self) -> Result<Vec<RouteData>, String> {
+        self.call(|s| s.il.fsm.routes.iter().cloned().map(|r| r.into()).collect())
             .await
-            .ok_or("not find a existed route")?;
-        debug!("寻得: {:?}", maybe_route.clone());
-
-        let close_node = fsm.node(start_sgn.toward_node_id).await;
-        debug!("接近区段: {}", close_node.node_id);
+    }
 
-        if close_node.state != NodeStatus::Vacant {
-            return Err("approching node is not vacant".into());
-        }
-        if close_node.is_lock {
-            return Err("not a complete route".into());
-        }
+    pub(crate) async fn audit_log(&self) -> Result<Vec<AuditEntry>, String> {
+        self.call(|s| s.audit.entries()).await
+    }
 
-        //鎖閉始端信號機
-        start_sgn.protect(&self.tx).await;
+    pub(crate) async fn incidents(&self) -> Result<Vec<IncidentReport>, String> {
+        self.call(|s| s.incidents.clone()).await
+    }
 
-        //解鎖所有節點
-        for n in maybe_route {
-            let mut node = fsm.node(n).await;
+    //注入或排除故障，記入審計
+    pub(crate) async fn inject_fault(&self, cmd: IlCommand) -> Result<()> {
+        self.call(move |s| {
+            let detail = format!("{:?}", cmd);
+            s.execute(cmd)?;
+            s.audit.record(s.tx.elapsed(), AuditKind::Fault, detail);
+            Ok(())
+        })
+        .await?
+        .map_err(|e: InterlockingError| e.extend())
+    }
 
-            node.unlock(&self.tx).await;
+    pub(crate) async fn release_emergency(&self) -> Result<()> {
+        self.call(|s| {
+            let released = s.il.fsm.emergency;
+            s.execute(IlCommand::ReleaseEmergency)?;
+            if released {
+                let timestamp = s.tx.elapsed();
+                s.audit.record(
+                    timestamp,
+                    AuditKind::EmergencyRelease,
+                    "emergency stop released".to_string(),
+                );
+            }
+            Ok(())
+        })
+        .await?
+        .map_err(|e: InterlockingError| e.extend())
+    }
 
-            let sgn_id = match start_dir {
-                RawDirection::Left => node.right_sgn_id.clone(),
-                RawDirection::Right => node.left_sgn_id.clone(),
-            };
+    pub(crate) async fn global_status(&self) -> Result<GlobalStatus, String> {
+        self.call(|s| s.il.get_global_status()).await
+    }
 
-            for id in self.topo.s_graph.neighbors(n) {
-                let mut node = fsm.node(id).await;
-                node.used_count -= 1; //对扩展集中的点解除征用
-            }
+    //連同進路和存儲進路的完整局面
+    pub(crate) async fn full_status(&self) -> Result<GlobalStatusData, String> {
+        self.call(|s| GlobalStatusData {
+            status: s.il.get_global_status(),
+            routes: s.il.fsm.routes.iter().cloned().map(|r| r.into()).collect(),
+            stored_routes: s.il.fsm.stored.clone().into(),
+        })
+        .await
+    }
 
-            //調車進路鎖閉所有調車信號機
-            if let Some(sgn_id) = sgn_id {
-                if sgn_id == start.id {
-                    continue;
+    //訂閲遊戲幀。給出since時從歷史中補發其後的幀，否則或缺口過大時先發送全局狀態；
+    //落後丟幀時重發全局狀態而不中斷。只持有弱引用，實例結束後訂閲隨之結束
+    pub(crate) fn frames(self: &Arc<Self>, since: Option<u64>) -> impl Stream<Item = Frame> {
+        let instance = Arc::downgrade(self);
+        //先訂閲再取歷史或狀態，期間的幀不會遺漏，重複的按序號跳過
+        let mut rx = self.tx.subscribe();
+        let backlog = since.and_then(|seq| self.tx.since(seq));
+
+        stream! {
+            let mut last = match (since, backlog) {
+                (Some(seq), Some(frames)) => {
+                    let mut last = seq;
+                    for frame in frames {
+                        last = frame.seq;
+                        yield frame;
+                    }
+                    last
                 }
-                let mut sgn = fsm.sgn(&sgn_id).await;
-                if sgn.kind == RawSignalKind::ShuntingSignal {
-                    sgn.protect(&self.tx).await;
+                _ => match status_frame(&instance).await {
+                    Some((seq, frame)) => {
+                        yield frame;
+                        seq
+                    }
+                    None => return,
+                },
+            };
+            loop {
+                match rx.recv().await {
+                    Ok(frame) if frame.seq <= last => continue,
+                    Ok(frame) => {
+                        last = frame.seq;
+                        yield frame;
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        debug!("subscriber lagged {} frames, resending global status", n);
+                        match status_frame(&instance).await {
+                            Some((seq, frame)) => {
+                                last = seq;
+                                yield frame;
+                            }
+                            None => break,
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
                 }
             }
         }
+    }
 
-        Ok(())
+    pub(crate) fn touch(&self) {
+        *self.last_active.lock().unwrap() = Utc::now();
     }
 
-    pub(crate) async fn find_a_route(
-        fsm: &InstanceFSM,
-        topo: &Topo,
-        nid: NodeID,
-        dir: &RawDirection,
-    ) -> Option<Vec<NodeID>> {
-        let curr = fsm.node(nid).await;
-        if !curr.is_lock || curr.state != NodeStatus::Vacant {
-            return None;
-        }
-        let mut res = vec![nid];
-        while let Some(next) = next_route_node(fsm, topo, &res, dir).await {
-            res.push(next);
+    pub(crate) fn idle_secs(&self) -> i64 {
+        (Utc::now() - *self.last_active.lock().unwrap()).num_seconds()
+    }
+}
+
+//實例的actor，依次執行命令，空閒時推進列車
+async fn run(mut state: InstanceState, mut commands: mpsc::Receiver<Command>) {
+    let mut ticker = interval(TICK_INTERVAL);
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => command(&mut state),
+                None => break,
+            },
+            _ = ticker.tick() => state.tick(),
         }
-        Some(res)
     }
 }
 
-pub(crate) async fn next_route_node(
-    fsm: &InstanceFSM,
-    topo: &Topo,
-    his: &Vec<NodeID>,
-    dir: &RawDirection,
-) -> Option<NodeID> {
-    let edges = topo.r_graph.edges(*his.last().unwrap());
-
-    for (_, t, d) in edges {
-        if d == dir && !his.contains(&t) {
-            let to = fsm.node(t).await;
-            if to.is_lock && to.state == NodeStatus::Vacant {
-                return Some(t);
+impl InstanceState {
+    pub(crate) fn build(cfg: &InstanceConfig) -> Result<(Self, LayoutData), String> {
+        let il = Interlocking::new(&cfg.station).map_err(|e| e.to_string())?;
+
+        //信號機的 dir 和 pos 缺省，按聯鎖推斷的方向配置
+        let stn_nodes: HashMap<_, NodeData> =
+            cfg.station.nodes.iter().map(|n| (n.id, n.into())).collect();
+        let mut signals = Vec::new();
+        for s in &cfg.station.signals {
+            let mut stn_sgn: SignalData = s.into();
+            let p_node_stn = stn_nodes
+                .get(&s.protect_node_id)
+                .ok_or(format!("unknown node id: {}", s.protect_node_id))?;
+
+            stn_sgn.dir = il.fsm.sgn(&s.id).dir;
+            stn_sgn.pos = match stn_sgn.dir {
+                RawDirection::Left => p_node_stn.left_p.clone(),
+                RawDirection::Right => p_node_stn.right_p.clone(),
+            };
+            signals.push(stn_sgn);
+        }
+
+        let layout = LayoutData {
+            title: cfg.station.title.clone(),
+            nodes: stn_nodes.values().cloned().collect(),
+            signals: signals,
+        };
+
+        let exam = if (&cfg.questions).is_empty() {
+            None
+        } else {
+            Some(ExamManager::new(&cfg.questions))
+        };
+
+        let state = InstanceState {
+            il: il,
+            exam: exam,
+            audit: AuditLog::default(),
+            incidents: Vec::new(),
+            counters: Counters::default(),
+            tx: FrameSender::new(),
+        };
+        Ok((state, layout))
+    }
+
+    //交給聯鎖執行，產生的事件作爲幀發出
+    fn execute(&mut self, cmd: IlCommand) -> Result<(), InterlockingError> {
+        let seq = self.il.fsm.route_seq;
+        for event in self.il.handle(cmd)? {
+            match &event {
+                Event::Alarm(alarm) => self.on_alarm(alarm),
+                //本次新建的自動通過進路只能是解鎖後重新建立的
+                Event::UpdateRoute(r) if r.auto && r.id > seq => {
+                    let detail = format!("auto route from {} re-established", r.start_sgn);
+                    self.audit
+                        .record(self.tx.elapsed(), AuditKind::AutoRoute, detail);
+                }
+                _ => {}
+            }
+            GameFrame::from(event).send_via(&self.tx);
+        }
+        Ok(())
+    }
+
+    //險情記入審計，考試中扣分
+    fn on_alarm(&mut self, alarm: &Alarm) {
+        match alarm.kind {
+            AlarmKind::Spad => {
+                let detail = format!(
+                    "train {:?} passed signal {} at danger into node {}",
+                    alarm.trains,
+                    alarm.signal.as_deref().unwrap_or("-"),
+                    alarm.node
+                );
+                self.audit
+                    .record(self.tx.elapsed(), AuditKind::Spad, detail);
+                if let Some(exam) = self.exam.as_mut() {
+                    exam.deduct(SPAD_PENALTY);
+                }
             }
+            AlarmKind::Collision | AlarmKind::Fouling => {
+                let kind = match alarm.kind {
+                    AlarmKind::Collision => AuditKind::Collision,
+                    _ => AuditKind::Fouling,
+                };
+                let timestamp = self.tx.elapsed();
+                let detail = format!("trains {:?} at node {}", alarm.trains, alarm.node);
+                self.audit.record(timestamp, kind, detail);
+                self.incidents
+                    .push(IncidentReport::new(timestamp, alarm, &self.il));
+            }
+        }
+    }
+
+    //鉛封按鈕計數，同時記入審計，供教師判斷操作是否必要
+    fn count(&mut self, kind: CounterKind, detail: String) {
+        let data = self.counters.count(kind);
+        self.audit
+            .record(self.tx.elapsed(), AuditKind::CriticalOperation, detail);
+        GameFrame::UpdateCounter(data).send_via(&self.tx);
+    }
+
+    fn tick(&mut self) {
+        if let Err(e) = self.execute(IlCommand::Tick) {
+            debug!("tick failed: {}", e);
         }
     }
-    None
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -419,6 +597,7 @@ pub(crate) struct InstanceConfig {
     pub(crate) id: String,
     pub(crate) title: String,
     pub(crate) player: String,
+    pub(crate) creator: Option<String>,
     pub(crate) station: RawStation,
     pub(crate) questions: HashMap<i32, QuestionModel>,
     pub(crate) token: String,
@@ -440,4 +619,154 @@ pub(crate) enum InstanceStatus {
     Finished, //已结束
 }
 
-pub type InstancePool = HashMap<String, Instance>;
+//當前完整局面，序號取狀態之前的最後一幀，之後的幀都會再補發
+async fn status_frame(instance: &Weak<Instance>) -> Option<(u64, Frame)> {
+    let instance = instance.upgrade()?;
+    let seq = instance.tx.last_seq();
+    let status = GameFrame::UpdateGlobalStatus(instance.full_status().await.ok()?);
+    Some((seq, instance.tx.frame_at(seq, status)))
+}
+
+pub type InstancePool = HashMap<String, Arc<Instance>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use uroj_interlocking::raw_station::RawButtonKind;
+
+    //一條直線上的六個區段，X接車進路爲 X -> S_L
+    fn config() -> InstanceConfig {
+        let nodes: Vec<String> = (1..=6)
+            .map(|i| {
+                let left: Vec<usize> = if i > 1 { vec![i - 1] } else { vec![] };
+                let right: Vec<usize> = if i < 6 { vec![i + 1] } else { vec![] };
+                let x = (i - 1) as f64 * 2.;
+                format!(
+                    r#"{{"id":{},"node_kind":"{}","turnout_id":[],"track_id":"{}G",
+                    "left_adj":{:?},"right_adj":{:?},"conflicted_nodes":[],
+                    "line":[[{},0],[{},0]],"joint":["NORMAL","NORMAL"]}}"#,
+                    i,
+                    if i == 3 || i == 4 {
+                        "MAINLINE"
+                    } else {
+                        "NORMAL"
+                    },
+                    if i == 4 { 3 } else { i },
+                    left,
+                    right,
+                    x,
+                    x + 2.
+                )
+            })
+            .collect();
+        let signal = |id: &str, kind: &str, protect: usize, toward: usize| {
+            format!(
+                r#"{{"id":"{}","side":"UPPER","sgn_kind":"{}","sgn_mnt":"POST_MOUNTING",
+                "protect_node_id":{},"toward_node_id":{},"btns":["TRAIN"]}}"#,
+                id, kind, protect, toward
+            )
+        };
+        let json = format!(
+            r#"{{"title":"test","nodes":[{}],"signals":[{},{}],"independent_btns":[]}}"#,
+            nodes.join(","),
+            signal("X", "HOME_SIGNAL", 2, 1),
+            signal("S_L", "STARTING_SIGNAL", 2, 3),
+        );
+
+        InstanceConfig {
+            id: "test".to_string(),
+            title: "test".to_string(),
+            player: "player".to_string(),
+            creator: Some("teacher".to_string()),
+            station: RawStation::from_json(&json).unwrap(),
+            questions: HashMap::new(),
+            token: "ABCDEF".to_string(),
+        }
+    }
+
+    fn train_btn(id: &str) -> PathBtn {
+        PathBtn {
+            id: id.to_string(),
+            kind: RawButtonKind::Train,
+        }
+    }
+
+    async fn create_receive_route(instance: &Instance) {
+        instance
+            .create_path(train_btn("X"), train_btn("S_L"))
+            .await
+            .unwrap();
+    }
+
+    fn route_count(frame: &Frame) -> Option<usize> {
+        match &frame.data {
+            GameFrame::UpdateGlobalStatus(s) => Some(s.routes.len()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands_through_actor() {
+        let instance = Instance::new(&config()).unwrap();
+        create_receive_route(&instance).await;
+
+        let routes = instance.routes().await.unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].start_sgn, "X");
+        //聯鎖拒絕的命令不影響actor繼續處理
+        assert!(instance
+            .create_path(train_btn("X"), train_btn("S_L"))
+            .await
+            .is_err());
+        assert_eq!(instance.routes().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_starts_with_status() {
+        let instance = Arc::new(Instance::new(&config()).unwrap());
+        create_receive_route(&instance).await;
+        let last = instance.tx.last_seq();
+        assert!(last > 0);
+
+        let mut frames = Box::pin(instance.frames(None));
+        let first = frames.next().await.unwrap();
+        assert!(first.seq >= last);
+        assert_eq!(route_count(&first), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_since() {
+        let instance = Arc::new(Instance::new(&config()).unwrap());
+        create_receive_route(&instance).await;
+        let last = instance.tx.last_seq();
+
+        //缺口在歷史内時逐幀補發
+        let mut frames = Box::pin(instance.frames(Some(0)));
+        for seq in 1..=last {
+            let frame = frames.next().await.unwrap();
+            assert_eq!(frame.seq, seq);
+            assert!(route_count(&frame).is_none());
+        }
+
+        //序號無法接續時改發全局狀態
+        let mut frames = Box::pin(instance.frames(Some(last + 10)));
+        let first = frames.next().await.unwrap();
+        assert!(first.seq >= last);
+        assert_eq!(route_count(&first), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_restore() {
+        let cfg = config();
+        let instance = Instance::new(&cfg).unwrap();
+        create_receive_route(&instance).await;
+
+        let json = serde_json::to_string(&instance.snapshot().await.unwrap()).unwrap();
+        let snapshot = serde_json::from_str(&json).unwrap();
+        let restored = Instance::restore(&cfg, &snapshot).unwrap();
+        let routes = restored.routes().await.unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].start_sgn, "X");
+    }
+}
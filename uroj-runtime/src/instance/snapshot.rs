@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uroj_interlocking::snapshot::StateSnapshot;
+
+use super::{
+    audit::AuditLog, counter::Counters, incident::IncidentReport, Instance, InstanceConfig,
+    InstanceState,
+};
+
+//實例快照，用於運行時重啓或遷移執行器後恢復
+#[derive(Deserialize, Serialize, Clone)]
+pub(crate) struct InstanceSnapshot {
+    pub(crate) elapsed: i64, //已運行的毫秒數
+    #[serde(flatten)]
+    pub(crate) state: StateSnapshot, //聯鎖局面
+    pub(crate) scores: HashMap<i32, i32>, //考試進度
+    #[serde(default)]
+    pub(crate) penalty: i32, //險情扣分
+    #[serde(default)]
+    pub(crate) audit: AuditLog,
+    #[serde(default)]
+    pub(crate) incidents: Vec<IncidentReport>,
+    #[serde(default)]
+    pub(crate) counters: Counters, //鉛封按鈕計數
+}
+
+impl Instance {
+    pub(crate) async fn snapshot(&self) -> Result<InstanceSnapshot, String> {
+        self.call(|s| s.snapshot()).await
+    }
+
+    //從快照恢復實例
+    pub(crate) fn restore(
+        cfg: &InstanceConfig,
+        snapshot: &InstanceSnapshot,
+    ) -> Result<Self, String> {
+        let (mut state, layout) = InstanceState::build(cfg)?;
+        state.restore(snapshot)?;
+        Ok(Instance::spawn(cfg, state, layout))
+    }
+}
+
+impl InstanceState {
+    pub(crate) fn snapshot(&self) -> InstanceSnapshot {
+        InstanceSnapshot {
+            elapsed: self.tx.elapsed(),
+            state: self.il.snapshot(),
+            scores: self
+                .exam
+                .as_ref()
+                .map(|e| e.score.clone())
+                .unwrap_or_default(),
+            penalty: self.exam.as_ref().map(|e| e.penalty).unwrap_or_default(),
+            audit: self.audit.clone(),
+            incidents: self.incidents.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &InstanceSnapshot) -> Result<(), String> {
+        self.tx.set_elapsed(snapshot.elapsed);
+
+        if let Some(exam) = self.exam.as_mut() {
+            exam.score = snapshot.scores.clone();
+            exam.penalty = snapshot.penalty;
+        }
+        self.audit = snapshot.audit.clone();
+        self.incidents = snapshot.incidents.clone();
+        self.counters = snapshot.counters.clone();
+
+        self.il.restore(&snapshot.state).map_err(|e| e.to_string())
+    }
+}
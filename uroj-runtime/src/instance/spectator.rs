@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_graphql::*;
+use chrono::{DateTime, Utc};
+
+use uroj_interlocking::fsm::GlobalStatus;
+
+use super::station::LayoutData;
+
+type SpectatorID = usize;
+
+//通過實例token旁觀的用户
+#[derive(SimpleObject, Clone)]
+pub(crate) struct Spectator {
+    pub(crate) id: SpectatorID,
+    pub(crate) name: Option<String>, //未登錄則爲空
+    pub(crate) joined_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct SpectatorTable {
+    next_id: SpectatorID,
+    spectators: HashMap<SpectatorID, Spectator>,
+}
+
+//當前旁觀者列表，訂閱結束時由SpectatorGuard移除
+#[derive(Clone, Default)]
+pub(crate) struct SpectatorList {
+    inner: Arc<Mutex<SpectatorTable>>,
+}
+
+impl SpectatorList {
+    pub(crate) fn join(&self, name: Option<String>) -> SpectatorGuard {
+        let mut table = self.inner.lock().unwrap();
+        table.next_id += 1;
+        let id = table.next_id;
+        table.spectators.insert(
+            id,
+            Spectator {
+                id: id,
+                name: name,
+                joined_at: Utc::now(),
+            },
+        );
+
+        SpectatorGuard {
+            id: id,
+            list: self.clone(),
+        }
+    }
+
+    pub(crate) fn list(&self) -> Vec<Spectator> {
+        let table = self.inner.lock().unwrap();
+        let mut spectators: Vec<_> = table.spectators.values().cloned().collect();
+        spectators.sort_by_key(|s| s.id);
+        spectators
+    }
+}
+
+pub(crate) struct SpectatorGuard {
+    id: SpectatorID,
+    list: SpectatorList,
+}
+
+impl Drop for SpectatorGuard {
+    fn drop(&mut self) {
+        self.list.inner.lock().unwrap().spectators.remove(&self.id);
+    }
+}
+
+//旁觀入口返回的實例信息
+#[derive(SimpleObject)]
+pub(crate) struct SpectateData {
+    pub(crate) id: String,
+    pub(crate) layout: LayoutData,
+    pub(crate) status: GlobalStatus,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_and_leave() {
+        let list = SpectatorList::default();
+        let anonymous = list.join(None);
+        let named = list.join(Some("guest".to_string()));
+
+        let spectators = list.list();
+        assert_eq!(spectators.len(), 2);
+        assert_eq!(spectators[0].name, None);
+        assert_eq!(spectators[1].name.as_deref(), Some("guest"));
+
+        //訂閲結束時移除
+        drop(anonymous);
+        let spectators = list.list();
+        assert_eq!(spectators.len(), 1);
+        assert_eq!(spectators[0].id, named.id);
+    }
+}
@@ -1,7 +1,11 @@
-use crate::raw_station::*;
 use async_graphql::*;
-
-use super::fsm::NodeID;
+use serde::{Deserialize, Serialize};
+use uroj_interlocking::{
+    fsm::{GlobalStatus, NodeID},
+    raw_station::*,
+    route::{Destination, Route, RouteID, RouteKind, RouteStatus},
+    storage::StoredRoute,
+};
 
 #[derive(SimpleObject, Clone)]
 pub(crate) struct Point {
@@ -69,7 +73,115 @@ impl From<&RawSignal> for SignalData {
     }
 }
 
+//可選的終端按鈕，供控制台高亮
+#[derive(SimpleObject, Clone)]
+pub(crate) struct DestinationData {
+    pub(crate) id: String, //信號機ID或者獨立按鈕ID
+    pub(crate) btn: ButtonKind,
+    pub(crate) kind: RouteKind,
+    pub(crate) path: Vec<NodeID>,
+}
+
+impl From<Destination> for DestinationData {
+    fn from(d: Destination) -> Self {
+        DestinationData {
+            id: d.id,
+            btn: (&d.btn).into(),
+            kind: d.kind,
+            path: d.path,
+        }
+    }
+}
+
+//已建立的進路
+#[derive(SimpleObject, Clone, Serialize, Deserialize)]
+pub(crate) struct RouteData {
+    pub(crate) id: RouteID,
+    pub(crate) kind: RouteKind,
+    pub(crate) start_sgn: String,
+    pub(crate) start_btn: ButtonKind,
+    pub(crate) end_id: String, //信號機ID或者獨立按鈕ID
+    pub(crate) end_btn: ButtonKind,
+    pub(crate) nodes: Vec<NodeID>,
+    pub(crate) reserved: Vec<NodeID>, //征用的牴觸區段
+    pub(crate) signals: Vec<String>,
+    pub(crate) state: RouteStatus,
+    pub(crate) released: Vec<NodeID>,
+    pub(crate) auto: bool, //自動通過進路
+    pub(crate) release_delay: u32, //總人解剩餘的延時
+}
+
+impl From<Route> for RouteData {
+    fn from(r: Route) -> Self {
+        RouteData {
+            id: r.id,
+            kind: r.kind,
+            start_sgn: r.start_sgn,
+            start_btn: (&r.start_btn).into(),
+            end_id: r.end_id,
+            end_btn: (&r.end_btn).into(),
+            nodes: r.nodes,
+            reserved: r.reserved,
+            signals: r.signals,
+            state: r.state,
+            released: r.released,
+            auto: r.auto,
+            release_delay: r.release_delay,
+        }
+    }
+}
+
+//存儲的進路，reason是阻礙的錯誤碼
+#[derive(SimpleObject, Clone, Serialize, Deserialize)]
+pub(crate) struct StoredRouteData {
+    pub(crate) id: RouteID,
+    pub(crate) start_sgn: String,
+    pub(crate) start_btn: ButtonKind,
+    pub(crate) end_id: String,
+    pub(crate) end_btn: ButtonKind,
+    pub(crate) reason: String,
+    pub(crate) message: String,
+    pub(crate) auto: bool, //等待重新建立的自動通過進路
+}
+
+impl From<StoredRoute> for StoredRouteData {
+    fn from(r: StoredRoute) -> Self {
+        StoredRouteData {
+            id: r.id,
+            start_sgn: r.start_sgn,
+            start_btn: (&r.start_btn).into(),
+            end_id: r.end_id,
+            end_btn: (&r.end_btn).into(),
+            reason: r.blocked_by.code().to_string(),
+            message: r.blocked_by.to_string(),
+            auto: r.auto,
+        }
+    }
+}
+
+//存儲進路的隊列，按存儲順序
+#[derive(SimpleObject, Clone, Serialize, Deserialize)]
+pub(crate) struct StoredRoutesData {
+    pub(crate) routes: Vec<StoredRouteData>,
+}
+
+impl From<Vec<StoredRoute>> for StoredRoutesData {
+    fn from(routes: Vec<StoredRoute>) -> Self {
+        StoredRoutesData {
+            routes: routes.into_iter().map(|r| r.into()).collect(),
+        }
+    }
+}
+
 // front models
+//完整的局面，新訂閲或丟幀後據此重建，不必依賴之前的幀
+#[derive(SimpleObject, Clone, Serialize, Deserialize)]
+pub(crate) struct GlobalStatusData {
+    pub(crate) status: GlobalStatus,
+    pub(crate) routes: Vec<RouteData>,
+    pub(crate) stored_routes: StoredRoutesData,
+}
+
 #[derive(SimpleObject, Clone)]
 pub(crate) struct LayoutData {
     pub(crate) title: String,
@@ -77,7 +189,7 @@ pub(crate) struct LayoutData {
     pub(crate) signals: Vec<SignalData>,
 }
 
-#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum ButtonKind {
     Pass,  //通過按鈕
     Shunt, //調車按鈕
@@ -97,3 +209,15 @@ impl From<&RawButtonKind> for ButtonKind {
         }
     }
 }
+
+impl From<ButtonKind> for RawButtonKind {
+    fn from(kind: ButtonKind) -> Self {
+        match kind {
+            ButtonKind::Pass => RawButtonKind::Pass,
+            ButtonKind::Shunt => RawButtonKind::Shunt,
+            ButtonKind::Train => RawButtonKind::Train,
+            ButtonKind::Guide => RawButtonKind::Guide,
+            ButtonKind::LZA => RawButtonKind::LZA,
+        }
+    }
+}
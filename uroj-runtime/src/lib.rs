@@ -1,7 +1,7 @@
 use actix_web::{guard, web};
 use async_graphql::{Context, Schema};
 use models::{AppSchema, Mutation, Query, Subscription};
-use std::{str::FromStr};
+use std::{str::FromStr, sync::Arc};
 use tokio::sync::{Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
 
 use uroj_common::utils::{Claims, Role as AuthRole};
@@ -20,15 +20,17 @@ pub fn configure_service(cfg: &mut web::ServiceConfig) {
     );
 }
 
-pub fn create_schema_with_context(db_pool: PgPool, ins_pool: InstancePool) -> AppSchema {
-    let mux_ins_pool = TokioMutex::new(ins_pool);
+pub fn create_instance_pool() -> SharedInstancePool {
+    Arc::new(TokioMutex::new(InstancePool::new()))
+}
 
+pub fn create_schema_with_context(db_pool: PgPool, ins_pool: SharedInstancePool) -> AppSchema {
     Schema::build(Query, Mutation, Subscription)
         // limits are commented out, because otherwise introspection query won't work
         // .limit_depth(3)
         // .limit_complexity(15)
         .data(db_pool)
-        .data(mux_ins_pool)
+        .data(ins_pool)
         .finish()
 }
 
@@ -47,21 +49,41 @@ pub(crate) fn get_id_from_ctx(ctx: &Context<'_>) -> Result<String, String> {
 
 pub(crate) fn get_role_from_ctx(ctx: &Context<'_>) -> Option<AuthRole> {
     ctx.data_opt::<Claims>()
-        .map(|c| AuthRole::from_str(&c.role).expect("Cannot parse authrole"))
+        .and_then(|c| AuthRole::from_str(&c.role).ok())
+}
+
+pub(crate) fn get_shared_pool_from_ctx<'ctx>(ctx: &Context<'ctx>) -> &'ctx SharedInstancePool {
+    ctx.data_unchecked::<SharedInstancePool>()
 }
 
 pub(crate) async fn get_instance_pool_from_ctx<'ctx>(
     ctx: &Context<'ctx>,
 ) -> TokioMutexGuard<'ctx, InstancePool> {
-    ctx.data_unchecked::<TokioMutex<InstancePool>>()
-        .lock()
-        .await
+    ctx.data_unchecked::<SharedInstancePool>().lock().await
 }
 
+//只在查找時持有實例池的鎖
+pub(crate) async fn get_instance_from_ctx(
+    ctx: &Context<'_>,
+    id: &str,
+) -> Result<Arc<instance::Instance>, String> {
+    get_instance_pool_from_ctx(ctx)
+        .await
+        .get(id)
+        .cloned()
+        .ok_or("no instance found".to_string())
+}
 
+mod auth;
+mod executor;
 mod handlers;
 mod instance;
+mod lifecycle;
 mod models;
-mod raw_station;
+mod supervisor;
 
 pub type InstancePool = instance::InstancePool;
+pub type SharedInstancePool = Arc<TokioMutex<InstancePool>>;
+
+pub use executor::spawn_heartbeat;
+pub use supervisor::spawn_supervisor;
@@ -0,0 +1,113 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+use async_graphql::Result;
+use log::info;
+use uroj_db::connection::Conn;
+use uroj_db::models::executor::Executor as ExecutorData;
+use uroj_db::models::instance::Instance as InstanceModel;
+use uroj_interlocking::raw_station::RawStation;
+
+use crate::{
+    executor::executor_addr,
+    instance::{snapshot::InstanceSnapshot, Instance, InstanceConfig, InstanceStatus},
+    SharedInstancePool,
+};
+
+pub(crate) fn load_config(data: &InstanceModel, conn: &Conn) -> Result<InstanceConfig> {
+    let scores = data.get_scores(conn)?;
+    let mut questions = HashMap::new();
+    for q in &scores {
+        questions.insert(q.question_id, q.get_question(conn)?);
+    }
+
+    let station_yaml = data.get_station(conn)?.yaml;
+
+    Ok(InstanceConfig {
+        id: data.id.to_string(),
+        title: data.title.clone(),
+        player: data.player_id.clone(),
+        creator: data.creator_id.clone(),
+        token: data.token.clone(),
+        station: RawStation::from_json(&station_yaml)?,
+        questions: questions,
+    })
+}
+
+//啓動實例，若已保存快照則從快照恢復
+pub(crate) async fn launch(
+    ins_pool: &SharedInstancePool,
+    data: &InstanceModel,
+    conn: &Conn,
+) -> Result<String> {
+    check_executor(data, conn)?;
+    let cfg = load_config(data, conn)?;
+    if ins_pool.lock().await.contains_key(&cfg.id) {
+        return Err(format!("instance {} is already running", cfg.id).into());
+    }
+
+    let instance = match &data.snapshot {
+        Some(s) => {
+            let snapshot = serde_json::from_str::<InstanceSnapshot>(s)?;
+            Instance::restore(&cfg, &snapshot)?
+        }
+        None => Instance::new(&cfg)?,
+    };
+    match ins_pool.lock().await.entry(cfg.id.clone()) {
+        Entry::Occupied(_) => {
+            return Err(format!("instance {} is already running", cfg.id).into());
+        }
+        Entry::Vacant(e) => {
+            e.insert(Arc::new(instance));
+        }
+    }
+
+    let state = InstanceStatus::Playing.to_string();
+    data.update_state(state.clone(), conn)?;
+    info!("running instance: {}", cfg.id.clone());
+
+    Ok(state)
+}
+
+//實例只能在分配給它的執行器上運行
+fn check_executor(data: &InstanceModel, conn: &Conn) -> Result<()> {
+    let addr = executor_addr();
+    let executor = ExecutorData::find_by_addr(&addr, conn)
+        .map_err(|_| format!("executor {} is not registered", addr))?;
+    if executor.id != data.executor_id {
+        return Err(format!(
+            "instance {} is assigned to executor {}, not {}",
+            data.id, data.executor_id, executor.id
+        )
+        .into());
+    }
+    Ok(())
+}
+
+//結束實例，寫回考試成績；全部寫入成功後才從實例池移除，失敗時可以重試
+pub(crate) async fn shutdown(
+    ins_pool: &SharedInstancePool,
+    data: &InstanceModel,
+    conn: &Conn,
+) -> Result<String> {
+    let id = data.id.to_string();
+    let running = ins_pool.lock().await.get(&id).cloned();
+    if let Some(instance) = &running {
+        if let Some(exam) = instance.call(|s| s.exam.clone()).await? {
+            exam.sync_score_to_db(&id, conn)?;
+        }
+    }
+
+    //不在内存中的實例也要結束，數據庫狀態不能停在運行中
+    let state = InstanceStatus::Finished.to_string();
+    data.update_state(state.clone(), conn)?;
+    data.update_snapshot(None, conn)?;
+    if running.is_some() {
+        ins_pool.lock().await.remove(&id);
+    }
+
+    info!("shut instance {} down", id);
+    Ok(state)
+}
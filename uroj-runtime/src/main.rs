@@ -2,16 +2,21 @@ use actix_cors::Cors;
 use actix_web::{App, HttpServer};
 use dotenv::dotenv;
 use uroj_db::{connection::create_connection_pool, run_migrations};
-use uroj_runtime::{configure_service, create_schema_with_context, InstancePool};
+use uroj_runtime::{
+    configure_service, create_instance_pool, create_schema_with_context, spawn_heartbeat,
+    spawn_supervisor,
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     env_logger::init();
 
-    let ins_pool = InstancePool::new();
+    let ins_pool = create_instance_pool();
     let db_pool = create_connection_pool();
     run_migrations(&db_pool);
+    spawn_heartbeat(ins_pool.clone());
+    spawn_supervisor(db_pool.clone(), ins_pool.clone());
     let schema = create_schema_with_context(db_pool, ins_pool);
 
     HttpServer::new(move || {
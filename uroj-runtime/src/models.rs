@@ -1,25 +1,34 @@
-use crate::{
-    get_conn_from_ctx, get_instance_pool_from_ctx,
-    instance::{fsm::GlobalStatus, InstanceKind},
+use crate::auth::{authorize, Permission};
+use crate::instance::{
+    audit::AuditEntry,
+    counter::CounterData,
+    exam::QuestionsData,
+    frame::Frame,
+    incident::IncidentReport,
+    spectator::{SpectateData, Spectator},
+    station::{ButtonKind, DestinationData, LayoutData, RouteData, StoredRoutesData},
+    {Instance, InstanceStatus},
 };
-use crate::{instance::next_route_node, raw_station::RawStation};
+use crate::lifecycle::{launch, shutdown};
 use crate::{
-    instance::{
-        exam::QuestionsData,
-        fsm::NodeID,
-        station::{ButtonKind, LayoutData},
-        GameFrame, PathBtn, {Instance, InstanceConfig, InstanceStatus},
-    },
-    raw_station::RawDirection,
+    get_conn_from_ctx, get_id_from_ctx, get_instance_from_ctx, get_instance_pool_from_ctx,
+    get_shared_pool_from_ctx, instance::InstanceKind,
 };
 use async_graphql::*;
 use async_stream::stream;
 use chrono::Utc;
 use futures::Stream;
 use log::{debug, info};
-use std::{collections::HashMap, str::FromStr, time::Duration};
-use tokio::time::delay_for;
-use uroj_db::models::instance::Instance as InstanceModel;
+use std::{str::FromStr, sync::Arc};
+use uroj_common::utils::get_random_token;
+use uroj_db::models::instance::{Instance as InstanceModel, NewInstance as NewInstanceModel};
+use uroj_db::models::instance_question::NewInstanceQuestion as NewInstanceQuestionModel;
+use uroj_interlocking::{
+    fsm::{GlobalStatus, NodeID, TurnoutID, TurnoutPosition},
+    raw_station::RawDirection,
+    route::{RouteID, RoutePlan},
+    Command as IlCommand, PathBtn,
+};
 
 use uuid::Uuid;
 
@@ -27,35 +36,133 @@ use uuid::Uuid;
 impl Query {
     //获取车站布局
     async fn station_layout(&self, ctx: &Context<'_>, id: String) -> Result<LayoutData> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
         let data = instance.layout.clone();
         Ok(data)
     }
 
     //获取考题信息
     async fn questions(&self, ctx: &Context<'_>, id: String) -> Result<QuestionsData> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
-        let data = instance.exam.as_ref().ok_or("not exam instance!")?;
-        Ok(data.get_questions())
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
+        let data = instance.questions.clone().ok_or("not exam instance!")?;
+        Ok(data)
     }
 
     async fn instance_type(&self, ctx: &Context<'_>, id: String) -> Result<InstanceKind> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
 
-        Ok(match instance.exam {
+        Ok(match instance.questions {
             Some(_) => InstanceKind::Exam,
             None => InstanceKind::Exercise,
         })
     }
 
     async fn global_status(&self, ctx: &Context<'_>, id: String) -> Result<GlobalStatus> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
-        let fsm = instance.fsm.lock().await;
-        Ok(fsm.get_global_status().await)
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
+        Ok(instance.global_status().await?)
+    }
+
+    //試排進路，不鎖閉任何區段，列出各項聯鎖條件的檢查結果，供練習時講解
+    async fn check_route(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: CreateRouteInput,
+    ) -> Result<RoutePlan> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        if instance.questions.is_some() {
+            return Err("not available in exam instance".into());
+        }
+
+        let (start, end) = input.into_buttons()?;
+        instance.check_route(start, end).await
+    }
+
+    //按下始端按鈕後可選的終端按鈕，僅用於練習
+    async fn destinations(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        start_btn: ButtonKind,
+        start_sgn: String,
+    ) -> Result<Vec<DestinationData>> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        if instance.questions.is_some() {
+            return Err("not available in exam instance".into());
+        }
+
+        let start = PathBtn {
+            id: start_sgn,
+            kind: start_btn.into(),
+        };
+        instance.destinations(start).await
+    }
+
+    //已建立的進路
+    async fn routes(&self, ctx: &Context<'_>, id: String) -> Result<Vec<RouteData>> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
+        Ok(instance.routes().await?)
+    }
+
+    //冒進等險情的審計記錄
+    async fn audit_log(&self, ctx: &Context<'_>, id: String) -> Result<Vec<AuditEntry>> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+        Ok(instance.audit_log().await?)
+    }
+
+    //鉛封按鈕的計數器
+    async fn counters(&self, ctx: &Context<'_>, id: String) -> Result<Vec<CounterData>> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
+        Ok(instance.counters().await?)
+    }
+
+    //衝突、侵限的事故報告
+    async fn incidents(&self, ctx: &Context<'_>, id: String) -> Result<Vec<IncidentReport>> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+        Ok(instance.incidents().await?)
+    }
+
+    //存儲的進路
+    async fn stored_routes(&self, ctx: &Context<'_>, id: String) -> Result<StoredRoutesData> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
+        Ok(instance.stored_routes().await?)
+    }
+
+    //獲取實例快照
+    async fn snapshot(&self, ctx: &Context<'_>, id: String) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+        Ok(serde_json::to_string(&instance.snapshot().await?)?)
+    }
+
+    //以實例token旁觀
+    async fn spectate(&self, ctx: &Context<'_>, token: String) -> Result<SpectateData> {
+        let (id, instance) = find_by_token(ctx, &token).await?;
+        let status = instance.global_status().await?;
+
+        Ok(SpectateData {
+            id: id,
+            layout: instance.layout.clone(),
+            status: status,
+        })
+    }
+
+    //當前的旁觀者
+    async fn spectators(&self, ctx: &Context<'_>, id: String) -> Result<Vec<Spectator>> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+        Ok(instance.spectators.list())
     }
 
     async fn ping(&self) -> String {
@@ -65,60 +172,45 @@ impl Query {
 
 #[Object]
 impl Mutation {
-    //运行
+    //运行，若有快照（傳入的或已保存的）則從快照恢復
     async fn run(&self, ctx: &Context<'_>, id: String) -> Result<String> {
         let uuid = Uuid::from_str(&id)?;
         let conn = get_conn_from_ctx(ctx);
         let data = InstanceModel::find_one(uuid, &conn)?;
+        //玩家和教師都可以啓動
+        authorize(
+            ctx,
+            Permission::Operate,
+            &data.player_id,
+            data.creator_id.as_deref(),
+            &data.token,
+        )
+        .or_else(|_| {
+            authorize(
+                ctx,
+                Permission::Instruct,
+                &data.player_id,
+                data.creator_id.as_deref(),
+                &data.token,
+            )
+        })?;
         //time bound
         if Utc::now().naive_local() < data.begin_at {
             return Err(format!("instance {} cannot be initialized yet", id).into());
         }
 
-        let scores = data.get_scores(&conn)?;
-        let mut questions = HashMap::new();
-        for q in &scores {
-            questions.insert(q.question_id, q.get_question(&conn)?);
-        }
-
-        let station_yaml = data.get_station(&conn)?.yaml;
-
-        let cfg = InstanceConfig {
-            id: data.id.to_string(),
-            title: data.title.clone(),
-            player: data.player_id.clone(),
-            token: data.token.clone(),
-            station: RawStation::from_json(&station_yaml)?,
-            questions: questions,
-        };
-
-        let mut pool = get_instance_pool_from_ctx(ctx).await;
-        if pool.contains_key(&cfg.id) {
-            return Err(format!("instance {} is already running", cfg.id).into());
-        }
-        let instance = Instance::new(&cfg)?;
-        pool.insert(cfg.id.clone(), instance);
-
-        let state = InstanceStatus::Playing.to_string();
-        data.update_state(state.clone(), &conn)?;
-        info!("running instance: {}", cfg.id.clone());
-
-        Ok(state)
+        launch(get_shared_pool_from_ctx(ctx), &data, &conn).await
     }
 
     //结束
     async fn stop(&self, ctx: &Context<'_>, id: String) -> Result<String> {
-        let mut instances = get_instance_pool_from_ctx(ctx).await;
-        instances
-            .remove(&id)
-            .ok_or(format!("not found instance {}", id))?;
+        get_instance_from_ctx(ctx, &id)
+            .await?
+            .authorize(ctx, Permission::Instruct)?;
         let uuid = Uuid::from_str(&id)?;
         let conn = get_conn_from_ctx(ctx);
-        let state = InstanceStatus::Finished.to_string();
-        InstanceModel::find_one(uuid, &conn)?.update_state(state.clone(), &conn)?;
-
-        info!("shut instance {} down", id.clone());
-        Ok(state)
+        let data = InstanceModel::find_one(uuid, &conn)?;
+        shutdown(get_shared_pool_from_ctx(ctx), &data, &conn).await
     }
 
     //創建進路
@@ -129,25 +221,45 @@ impl Mutation {
         input: CreateRouteInput,
     ) -> Result<String> {
         debug!("request for creating route");
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
 
-        let start = PathBtn {
-            id: input.start_sgn,
-            kind: input.start_btn,
-        };
-        let end = PathBtn {
-            kind: input.end_btn,
-            id: match input.end_btn {
-                ButtonKind::Train | ButtonKind::Shunt => input.end_sgn.ok_or("error input")?,
-                ButtonKind::LZA => input.end_ind_btn.ok_or("error input")?,
-                _ => return Err("no valid route".into()),
-            },
-        };
+        let (start, end) = input.into_buttons()?;
+        instance.create_path(start, end).await?;
+        info!("new route in instance {}", id.clone());
+
+        Ok(id)
+    }
 
-        let path = instance.create_path(start, end).await?;
-        info!("new route {:?} in instance {}", path, id.clone());
+    //存儲進路，暫時不能建立時排隊，條件滿足後自動建立
+    async fn store_route(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: CreateRouteInput,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
 
+        let (start, end) = input.into_buttons()?;
+        instance.store_path(start, end).await?;
+        Ok(id)
+    }
+
+    //取消存儲的進路
+    async fn cancel_stored_route(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        route: RouteID,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+
+        instance.cancel_stored(route).await?;
         Ok(id)
     }
 
@@ -158,18 +270,39 @@ impl Mutation {
         id: String,
         input: CancelRouteInput,
     ) -> Result<String> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
 
         let start = PathBtn {
             id: input.start_sgn,
-            kind: input.start_btn,
+            kind: input.start_btn.into(),
         };
 
         instance.cancel_path(start).await?;
         Ok(id)
     }
 
+    //自動通過進路的開關
+    async fn set_auto_route(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: CancelRouteInput,
+        auto: bool,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+
+        let start = PathBtn {
+            id: input.start_sgn,
+            kind: input.start_btn.into(),
+        };
+        instance.set_auto_route(start, auto).await?;
+        Ok(id)
+    }
+
     //人工解鎖
     async fn manually_unlock(
         &self,
@@ -177,62 +310,227 @@ impl Mutation {
         id: String,
         input: CancelRouteInput,
     ) -> Result<String> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
 
         let start = PathBtn {
             id: input.start_sgn,
-            kind: input.start_btn,
+            kind: input.start_btn.into(),
         };
 
-        instance.cancel_path(start).await?;
+        instance.manually_release(start).await?;
         Ok(id)
     }
 
     //區間故障解鎖
     async fn fault_unlock(&self, ctx: &Context<'_>, id: String, node: NodeID) -> Result<String> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
-        // instance.create_path();
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+        instance.fault_release(node).await?;
 
         Ok(id)
     }
 
-    async fn spawn_train(&self, ctx: &Context<'_>, id: String, at: NodeID) -> Result<String> {
-        let mut pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get_mut(&id).ok_or("no instance found")?;
-        let mut fsm = instance.fsm.lock().await;
-        let topo = instance.topo.clone();
-
-        let arc_fsm = instance.fsm.clone();
-        let sender = instance.tx.clone();
-        let arc_train = fsm.spawn_train(at, &sender).await;
-
-        tokio::spawn(async move {
-            let mut train = arc_train.lock().await;
-            loop {
-                let fsm = &arc_fsm.lock().await;
-                let next_node = next_route_node(&fsm, &topo, &train.past_node, &RawDirection::Left)
-                    .await
-                    .map(|n| (n, RawDirection::Left))
-                    .or(
-                        next_route_node(&fsm, &topo, &train.past_node, &RawDirection::Right)
-                            .await
-                            .map(|n| (n, RawDirection::Right)),
-                    );
-
-                if let Some((node, dir)) = next_node {
-                    train.turn_direction(dir);
-                    train.try_next_step(node, &fsm, &topo, &sender).await;
-                    delay_for(Duration::from_millis(1)).await;
-                } else {
-                    break; //找不到次一个结点则返回
-                };
-            }
-        });
+    //開放引導信號
+    async fn open_guide(&self, ctx: &Context<'_>, id: String, sgn: String) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+        instance.open_guide(sgn).await?;
+
+        Ok(id)
+    }
+
+    //道岔強扳
+    async fn force_turnout(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        turnout: TurnoutID,
+        position: TurnoutPosition,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+        instance.force_turnout(turnout, position).await?;
+
+        Ok(id)
+    }
+
+    //區段封鎖，封鎖的區段不能排入進路
+    async fn block_node(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        node: NodeID,
+        blocked: bool,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+
+        instance.block_node(node, blocked).await?;
+        Ok(id)
+    }
+
+    //按鈕封鎖，封鎖的信號機不能作爲進路始終端
+    async fn block_signal(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        sgn: String,
+        blocked: bool,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+
+        instance.block_signal(sgn, blocked).await?;
+        Ok(id)
+    }
+
+    //股道封鎖
+    async fn block_track(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        track: String,
+        blocked: bool,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Operate)?;
+        instance.touch();
+
+        instance.block_track(track, blocked).await?;
+        Ok(id)
+    }
+
+    //overrun的列車不理會信號，用於冒進演練
+    async fn spawn_train(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        at: NodeID,
+        dir: RawDirection,
+        overrun: Option<bool>,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+        instance
+            .spawn_train(at, dir, overrun.unwrap_or(false))
+            .await?;
+
+        Ok(id)
+    }
+
+    //注入或排除軌道電路故障、主燈絲斷絲，僅出題人或管理員
+    async fn inject_fault(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: FaultInput,
+    ) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+
+        let cmd = match (input.node, input.signal) {
+            (Some(node), None) => IlCommand::SetTrackFault {
+                node: node,
+                fault: input.active,
+            },
+            (None, Some(sgn)) => IlCommand::SetFilamentFault {
+                sgn: sgn,
+                fused: input.active,
+            },
+            _ => return Err("exactly one of node and signal is required".into()),
+        };
+        instance.inject_fault(cmd).await?;
+
+        Ok(id)
+    }
+
+    //解除緊急停車，列車恢復運行
+    async fn release_emergency(&self, ctx: &Context<'_>, id: String) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+        instance.release_emergency().await?;
+
+        Ok(id)
+    }
+
+    //保存快照，重新運行時從快照恢復
+    async fn save_snapshot(&self, ctx: &Context<'_>, id: String) -> Result<String> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::Instruct)?;
+        let snapshot = serde_json::to_string(&instance.snapshot().await?)?;
 
+        let uuid = Uuid::from_str(&id)?;
+        let conn = get_conn_from_ctx(ctx);
+        InstanceModel::find_one(uuid, &conn)?.update_snapshot(Some(snapshot), &conn)?;
+
+        info!("saved snapshot of instance {}", id.clone());
         Ok(id)
     }
+
+    //以當前局面派生新實例，車站相同，玩家不同，考試進度重置
+    async fn fork(&self, ctx: &Context<'_>, id: String, input: ForkInput) -> Result<String> {
+        let creator = get_id_from_ctx(ctx)?;
+        let uuid = Uuid::from_str(&id)?;
+        let conn = get_conn_from_ctx(ctx);
+        let data = InstanceModel::find_one(uuid, &conn)?;
+
+        let source = get_instance_from_ctx(ctx, &id).await?;
+        source.authorize(ctx, Permission::Instruct)?;
+        let mut snapshot = source.snapshot().await?;
+        snapshot.scores.clear();
+        snapshot.penalty = 0;
+        snapshot.audit = Default::default();
+        snapshot.incidents.clear();
+        snapshot.counters = Default::default();
+
+        //副本沿用原實例的時間安排和執行器
+        let new_instance = NewInstanceModel {
+            title: input.title.unwrap_or(data.title.clone()),
+            description: data.description.clone(),
+            creator_id: creator,
+            player_id: input.player,
+            station_id: data.station_id,
+            curr_state: InstanceStatus::Prestart.to_string(),
+            executor_id: data.executor_id,
+            token: get_random_token(),
+            begin_at: Some(data.begin_at),
+            end_at: data.end_at,
+        }
+        .create(&conn)?;
+
+        for q in data.get_scores(&conn)? {
+            NewInstanceQuestionModel {
+                instance_id: new_instance.id,
+                question_id: q.question_id,
+                score: None,
+            }
+            .create(&conn)?;
+        }
+
+        //先保存快照，啓動失敗時監督者也能從快照恢復
+        new_instance.update_snapshot(Some(serde_json::to_string(&snapshot)?), &conn)?;
+        let new_instance = InstanceModel::find_one(new_instance.id, &conn)?;
+        launch(get_shared_pool_from_ctx(ctx), &new_instance, &conn).await?;
+        info!("forked instance {} from {}", new_instance.id, id);
+
+        Ok(new_instance.id.to_string())
+    }
+}
+
+async fn find_by_token(ctx: &Context<'_>, token: &str) -> Result<(String, Arc<Instance>)> {
+    get_instance_pool_from_ctx(ctx)
+        .await
+        .iter()
+        .find(|(_, i)| i.token == token)
+        .map(|(id, i)| (id.clone(), i.clone()))
+        .ok_or("no instance found".into())
 }
 
 //tag 可以是信號機ID或者獨立ButtonID
@@ -245,6 +543,37 @@ struct CreateRouteInput {
     end_ind_btn: Option<String>,
 }
 
+impl CreateRouteInput {
+    fn into_buttons(self) -> Result<(PathBtn, PathBtn)> {
+        let start = PathBtn {
+            id: self.start_sgn,
+            kind: self.start_btn.into(),
+        };
+        let end = PathBtn {
+            kind: self.end_btn.into(),
+            id: match self.end_btn {
+                ButtonKind::Train | ButtonKind::Shunt => self.end_sgn.ok_or("error input")?,
+                ButtonKind::LZA => self.end_ind_btn.ok_or("error input")?,
+                _ => return Err("no valid route".into()),
+            },
+        };
+        Ok((start, end))
+    }
+}
+
+#[derive(InputObject)]
+struct ForkInput {
+    player: String,
+    title: Option<String>,
+}
+
+#[derive(InputObject)]
+struct FaultInput {
+    node: Option<NodeID>,   //軌道電路故障的區段
+    signal: Option<String>, //主燈絲斷絲的信號機
+    active: bool,           //注入或排除
+}
+
 #[derive(InputObject)]
 struct CancelRouteInput {
     start_btn: ButtonKind,
@@ -257,14 +586,39 @@ impl Subscription {
         &self,
         ctx: &'ctx Context<'_>,
         id: String,
-    ) -> Result<impl Stream<Item = GameFrame>> {
-        let pool = get_instance_pool_from_ctx(ctx).await;
-        let instance = pool.get(&id).ok_or("no instance found")?;
-        let mut stream = instance.tx.subscribe();
+        since: Option<u64>, //斷線重連時傳入已收到的最後一幀序號
+    ) -> Result<impl Stream<Item = Frame>> {
+        let instance = get_instance_from_ctx(ctx, &id).await?;
+        instance.authorize(ctx, Permission::View)?;
+        let guard = if instance.is_spectator(ctx) {
+            Some(instance.spectators.join(get_id_from_ctx(ctx).ok()))
+        } else {
+            None
+        };
+        let frames = instance.frames(since);
+
+        Ok(stream! {
+            let _guard = guard;
+            for await frame in frames {
+                yield frame;
+            }
+        })
+    }
+
+    //旁觀者的訂閲，訂閲期間計入旁觀者列表
+    async fn spectate_update<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        token: String,
+    ) -> Result<impl Stream<Item = Frame>> {
+        let (_, instance) = find_by_token(ctx, &token).await?;
+        let guard = instance.spectators.join(get_id_from_ctx(ctx).ok());
+        let frames = instance.frames(None);
 
         Ok(stream! {
-            while let Ok(value) = stream.recv().await {
-                yield value;
+            let _guard = guard;
+            for await frame in frames {
+                yield frame;
             }
         })
     }
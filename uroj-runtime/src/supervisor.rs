@@ -0,0 +1,179 @@
+use std::{collections::HashSet, env, str::FromStr, time::Duration};
+
+use actix_web::rt::time::interval;
+use async_graphql::Result;
+use chrono::Utc;
+use log::{info, warn};
+use uroj_db::connection::{Conn, PgPool};
+use uroj_db::models::executor::Executor as ExecutorData;
+use uroj_db::models::instance::Instance as InstanceModel;
+
+use crate::{
+    executor::executor_addr,
+    instance::{Instance, InstanceStatus},
+    lifecycle::{launch, shutdown},
+    SharedInstancePool,
+};
+
+const SUPERVISE_INTERVAL_SECS: u64 = 15;
+//玩家無操作超過此時長則結束實例，可由 INSTANCE_IDLE_TIMEOUT_SECS 覆蓋
+const DEFAULT_IDLE_TIMEOUT_SECS: i64 = 1800;
+
+//定時對照數據庫管理本執行器上的實例：
+//到點啓動、到期或閒置結束、崩潰後恢復、自動保存快照
+pub fn spawn_supervisor(db_pool: PgPool, ins_pool: SharedInstancePool) {
+    let addr = executor_addr();
+    let idle_timeout = env::var("INSTANCE_IDLE_TIMEOUT_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS);
+
+    actix_web::rt::spawn(async move {
+        let mut interval = interval(Duration::from_secs(SUPERVISE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = supervise(&db_pool, &ins_pool, &addr, idle_timeout).await {
+                warn!("supervisor failed: {}", e.message);
+            }
+        }
+    });
+}
+
+async fn supervise(
+    db_pool: &PgPool,
+    ins_pool: &SharedInstancePool,
+    addr: &str,
+    idle_timeout: i64,
+) -> Result<()> {
+    let conn = db_pool.get()?;
+    //尚未註冊時等下一輪
+    let executor = match ExecutorData::find_by_addr(addr, &conn) {
+        Ok(e) => e,
+        Err(_) => return Ok(()),
+    };
+
+    let now = Utc::now().naive_utc();
+    let launched: HashSet<String> = ins_pool.lock().await.keys().cloned().collect();
+    let mut assigned = HashSet::new();
+
+    for data in InstanceModel::list_by_executor(executor.id, &conn)? {
+        let id = data.id.to_string();
+        assigned.insert(id.clone());
+
+        //只在查找時持有實例池的鎖
+        let running = ins_pool.lock().await.get(&id).cloned();
+        let expired = data.end_at.map_or(false, |t| t <= now);
+        let status = InstanceStatus::from_str(&data.curr_state)?;
+        let idle_secs = running.as_ref().map(|i| i.idle_secs());
+        let action = decide(
+            status,
+            data.begin_at <= now,
+            expired,
+            idle_secs,
+            idle_timeout,
+        );
+        let result = match (action, &running) {
+            (Action::Launch, _) => {
+                info!("launching instance {}", id);
+                launch(ins_pool, &data, &conn).await.map(|_| ())
+            }
+            (Action::Shutdown, _) => {
+                info!("shutting instance {} down", id);
+                shutdown(ins_pool, &data, &conn).await.map(|_| ())
+            }
+            (Action::Autosave, Some(instance)) => autosave(instance, &data, &conn).await,
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            warn!("supervisor failed on instance {}: {}", id, e.message);
+        }
+    }
+
+    //數據庫中已不屬於本執行器的實例，本輪查詢之後才啓動的不在此列
+    ins_pool.lock().await.retain(|id, _| {
+        let keep = assigned.contains(id) || !launched.contains(id);
+        if !keep {
+            info!("dropping unassigned instance {}", id);
+        }
+        keep
+    });
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Launch,
+    Shutdown,
+    Autosave,
+    Keep,
+}
+
+//按數據庫狀態和内存中的實例決定本輪的處理，idle_secs 爲空表示實例不在内存中
+fn decide(
+    status: InstanceStatus,
+    started: bool,
+    expired: bool,
+    idle_secs: Option<i64>,
+    idle_timeout: i64,
+) -> Action {
+    match status {
+        //到期結束
+        InstanceStatus::Prestart | InstanceStatus::Playing if expired => Action::Shutdown,
+        //到點啓動
+        InstanceStatus::Prestart if started => Action::Launch,
+        InstanceStatus::Playing => match idle_secs {
+            //數據庫中仍在運行而内存中沒有，多爲執行器崩潰重啓，從快照恢復
+            None => Action::Launch,
+            //閒置過久
+            Some(idle) if idle >= idle_timeout => Action::Shutdown,
+            Some(_) => Action::Autosave,
+        },
+        //已在別處結束，寫回成績後移除
+        InstanceStatus::Finished if idle_secs.is_some() => Action::Shutdown,
+        _ => Action::Keep,
+    }
+}
+
+async fn autosave(instance: &Instance, data: &InstanceModel, conn: &Conn) -> Result<()> {
+    let snapshot = serde_json::to_string(&instance.snapshot().await?)?;
+    data.update_snapshot(Some(snapshot), conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: i64 = 60;
+
+    #[test]
+    fn test_prestart() {
+        let action =
+            |started, expired| decide(InstanceStatus::Prestart, started, expired, None, TIMEOUT);
+        assert_eq!(action(false, false), Action::Keep);
+        assert_eq!(action(true, false), Action::Launch);
+        assert_eq!(action(true, true), Action::Shutdown);
+        //未開始就過期的也要結束
+        assert_eq!(action(false, true), Action::Shutdown);
+    }
+
+    #[test]
+    fn test_playing() {
+        let action = |expired, idle| decide(InstanceStatus::Playing, true, expired, idle, TIMEOUT);
+        assert_eq!(action(false, Some(0)), Action::Autosave);
+        assert_eq!(action(false, Some(TIMEOUT)), Action::Shutdown);
+        assert_eq!(action(true, Some(0)), Action::Shutdown);
+        //崩潰後恢復，過期的則直接結束
+        assert_eq!(action(false, None), Action::Launch);
+        assert_eq!(action(true, None), Action::Shutdown);
+    }
+
+    #[test]
+    fn test_finished() {
+        let action = |idle| decide(InstanceStatus::Finished, true, true, idle, TIMEOUT);
+        assert_eq!(action(Some(0)), Action::Shutdown);
+        assert_eq!(action(None), Action::Keep);
+    }
+}